@@ -0,0 +1,482 @@
+// Game settings read from a TOML file so they can be changed without rebuilding. A match is
+// played with the host's: they go to the joiner when the match starts, and the joiner answers
+// with a hash of what it got so the host knows both sides are simulating the same game.
+
+use std::f32::consts::FRAC_PI_2;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::fnv1a;
+use crate::sim::{MatchRules, PongGameConfig, ServeOrder, Vector2, GAME_CONFIG};
+
+/// Where the game looks for its settings, relative to where it was started from
+pub const CONFIG_FILE: &str = "pong.toml";
+
+// the relay and the game each keep the last 512 inputs to resend after a reconnect, and an
+// opponent is allowed up to twice max_rollback_frames ahead, so this is as many as fit
+const MOST_ROLLBACK_FRAMES: usize = 256;
+
+// points_to_win, win_by_two, time_limit_frames, best_of and serve_order
+const ENCODED_RULES_SIZE: usize = 4 + 1 + 4 + 4 + 1;
+
+/// Every field of a PongGameConfig as 4 little endian bytes, in declaration order, then its rules
+pub const ENCODED_CONFIG_SIZE: usize = 16 * 4 + ENCODED_RULES_SIZE;
+
+/// Why a config can't be played with
+#[derive(Clone, PartialEq, Debug)]
+pub enum ConfigError {
+    // the file couldn't be read, or isn't TOML with the fields of a PongGameConfig
+    Unreadable(String),
+    NotFinite { field: &'static str },
+    NotPositive { field: &'static str },
+    Negative { field: &'static str },
+    // the ball has to leave a paddle heading back across the court
+    BounceAngleOutOfRange { angle: f32 },
+    // the game has to be able to run at least a frame ahead of the opponent, and not so far
+    // that a reconnect can't catch up
+    RollbackFramesOutOfRange { frames: usize },
+    RollbackFramesNotPowerOfTwo { frames: usize },
+    // the paddles or the ball don't fit in the arena
+    DoesntFit { what: &'static str },
+    // only in settings someone else encoded, a file has to name one
+    UnknownServeOrder { value: u8 },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Unreadable(reason) => write!(f, "can't read the config: {}", reason),
+            ConfigError::NotFinite { field } => write!(f, "{} is not a finite number", field),
+            ConfigError::NotPositive { field } => write!(f, "{} has to be more than 0", field),
+            ConfigError::Negative { field } => write!(f, "{} can't be negative", field),
+            ConfigError::BounceAngleOutOfRange { angle } => write!(
+                f,
+                "max_bounce_angle {} has to be from 0 up to, but not including, {}",
+                angle, FRAC_PI_2
+            ),
+            ConfigError::RollbackFramesOutOfRange { frames } => write!(
+                f,
+                "max_rollback_frames {} has to be from 3 to {}",
+                frames, MOST_ROLLBACK_FRAMES
+            ),
+            ConfigError::RollbackFramesNotPowerOfTwo { frames } => {
+                write!(f, "max_rollback_frames {} has to be a power of two", frames)
+            }
+            ConfigError::DoesntFit { what } => write!(f, "{} doesn't fit in the arena", what),
+            ConfigError::UnknownServeOrder { value } => {
+                write!(f, "serve order {} isn't one the game knows", value)
+            }
+        }
+    }
+}
+
+fn check_finite(field: &'static str, value: f32) -> Result<(), ConfigError> {
+    if value.is_finite() {
+        Ok(())
+    } else {
+        Err(ConfigError::NotFinite { field })
+    }
+}
+
+fn check_positive(field: &'static str, value: f32) -> Result<(), ConfigError> {
+    check_finite(field, value)?;
+    if value > 0.0 {
+        Ok(())
+    } else {
+        Err(ConfigError::NotPositive { field })
+    }
+}
+
+fn check_not_negative(field: &'static str, value: f32) -> Result<(), ConfigError> {
+    check_finite(field, value)?;
+    if value >= 0.0 {
+        Ok(())
+    } else {
+        Err(ConfigError::Negative { field })
+    }
+}
+
+impl PongGameConfig {
+    /// Reads the settings from a TOML file, see `from_toml`. No file at all is the same as an
+    /// empty one, the defaults
+    pub fn load(path: &Path) -> Result<PongGameConfig, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(text) => PongGameConfig::from_toml(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(GAME_CONFIG),
+            Err(e) => Err(ConfigError::Unreadable(e.to_string())),
+        }
+    }
+
+    /// Any field left out keeps its value from GAME_CONFIG, vectors are written as
+    /// `{ x = 1.0, y = 2.0 }` and the match rules go in a `[rules]` table. Only settings that
+    /// pass `validate` come back
+    pub fn from_toml(text: &str) -> Result<PongGameConfig, ConfigError> {
+        let config: PongGameConfig =
+            toml::from_str(text).map_err(|e| ConfigError::Unreadable(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks for settings the simulation can't run with. The joiner checks the host's too,
+    /// they can't be trusted any more than the host's inputs
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_positive("arena_size.x", self.arena_size.x)?;
+        check_positive("arena_size.y", self.arena_size.y)?;
+        check_positive("paddle_size.x", self.paddle_size.x)?;
+        check_positive("paddle_size.y", self.paddle_size.y)?;
+        check_positive("paddle_force", self.paddle_force)?;
+        check_not_negative("paddle_friction", self.paddle_friction)?;
+        check_positive("ball_size", self.ball_size)?;
+        check_positive("ball_speed", self.ball_speed)?;
+        check_positive("dt", self.dt)?;
+        check_not_negative("serve_spread", self.serve_spread)?;
+        check_finite("paddle_spin", self.paddle_spin)?;
+        if self.score_font_size <= 0 {
+            return Err(ConfigError::NotPositive {
+                field: "score_font_size",
+            });
+        }
+        if !(0.0..FRAC_PI_2).contains(&self.max_bounce_angle) {
+            return Err(ConfigError::BounceAngleOutOfRange {
+                angle: self.max_bounce_angle,
+            });
+        }
+        // the game waits for the opponent 2 frames short of the rollback window
+        if !(3..=MOST_ROLLBACK_FRAMES).contains(&self.max_rollback_frames) {
+            return Err(ConfigError::RollbackFramesOutOfRange {
+                frames: self.max_rollback_frames,
+            });
+        }
+        if !self.max_rollback_frames.is_power_of_two() {
+            return Err(ConfigError::RollbackFramesNotPowerOfTwo {
+                frames: self.max_rollback_frames,
+            });
+        }
+        if self.paddle_size.y >= self.arena_size.y {
+            return Err(ConfigError::DoesntFit { what: "a paddle" });
+        }
+        if self.paddle_size.x * 2.0 + self.ball_size * 2.0 >= self.arena_size.x {
+            return Err(ConfigError::DoesntFit {
+                what: "the ball between the paddles",
+            });
+        }
+        if self.ball_size * 2.0 >= self.arena_size.y {
+            return Err(ConfigError::DoesntFit { what: "the ball" });
+        }
+        self.rules.validate()
+    }
+
+    /// For sending to the joiner and saving in replays
+    pub fn encode(&self) -> [u8; ENCODED_CONFIG_SIZE] {
+        let fields = [
+            self.arena_size.x.to_le_bytes(),
+            self.arena_size.y.to_le_bytes(),
+            self.paddle_size.x.to_le_bytes(),
+            self.paddle_size.y.to_le_bytes(),
+            self.paddle_force.to_le_bytes(),
+            self.paddle_friction.to_le_bytes(),
+            self.ball_size.to_le_bytes(),
+            self.ball_speed.to_le_bytes(),
+            self.score_font_size.to_le_bytes(),
+            (self.max_rollback_frames as u32).to_le_bytes(),
+            self.dt.to_le_bytes(),
+            self.serve_countdown_frames.to_le_bytes(),
+            self.serve_hold_frames.to_le_bytes(),
+            self.serve_spread.to_le_bytes(),
+            self.max_bounce_angle.to_le_bytes(),
+            self.paddle_spin.to_le_bytes(),
+        ];
+        let mut out = [0u8; ENCODED_CONFIG_SIZE];
+        for (chunk, field) in out.chunks_exact_mut(4).zip(fields.iter()) {
+            chunk.copy_from_slice(field);
+        }
+        out[fields.len() * 4..].copy_from_slice(&self.rules.encode());
+        out
+    }
+
+    /// What `encode` wrote. Doesn't validate, whoever receives it has to
+    pub fn decode(bytes: &[u8; ENCODED_CONFIG_SIZE]) -> Result<PongGameConfig, ConfigError> {
+        let field = |i: usize| {
+            [
+                bytes[i * 4],
+                bytes[i * 4 + 1],
+                bytes[i * 4 + 2],
+                bytes[i * 4 + 3],
+            ]
+        };
+        let f32 = |i: usize| f32::from_le_bytes(field(i));
+        let u32 = |i: usize| u32::from_le_bytes(field(i));
+        Ok(PongGameConfig {
+            arena_size: Vector2::new(f32(0), f32(1)),
+            paddle_size: Vector2::new(f32(2), f32(3)),
+            paddle_force: f32(4),
+            paddle_friction: f32(5),
+            ball_size: f32(6),
+            ball_speed: f32(7),
+            score_font_size: u32(8) as i32,
+            max_rollback_frames: u32(9) as usize,
+            dt: f32(10),
+            serve_countdown_frames: u32(11),
+            serve_hold_frames: u32(12),
+            serve_spread: f32(13),
+            max_bounce_angle: f32(14),
+            paddle_spin: f32(15),
+            rules: MatchRules::decode(&bytes[16 * 4..])?,
+        })
+    }
+
+    /// A fingerprint of every setting, the same on every machine. Two players whose configs
+    /// hash the same are simulating the same game
+    pub fn config_hash(&self) -> u64 {
+        fnv1a(&self.encode())
+    }
+}
+
+impl MatchRules {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.points_to_win <= 0 {
+            return Err(ConfigError::NotPositive {
+                field: "rules.points_to_win",
+            });
+        }
+        // 0 is how no time limit is encoded
+        if self.time_limit_frames == Some(0) {
+            return Err(ConfigError::NotPositive {
+                field: "rules.time_limit_frames",
+            });
+        }
+        if self.best_of == 0 {
+            return Err(ConfigError::NotPositive {
+                field: "rules.best_of",
+            });
+        }
+        Ok(())
+    }
+
+    // whole numbers stay whole numbers, no time limit is 0
+    fn encode(&self) -> [u8; ENCODED_RULES_SIZE] {
+        let mut out = [0u8; ENCODED_RULES_SIZE];
+        out[0..4].copy_from_slice(&self.points_to_win.to_le_bytes());
+        out[4] = self.win_by_two as u8;
+        out[5..9].copy_from_slice(&self.time_limit_frames.unwrap_or(0).to_le_bytes());
+        out[9..13].copy_from_slice(&self.best_of.to_le_bytes());
+        out[13] = match self.serve_order {
+            ServeOrder::Loser => 0,
+            ServeOrder::Alternate => 1,
+        };
+        out
+    }
+
+    fn decode(bytes: &[u8]) -> Result<MatchRules, ConfigError> {
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let serve_order = match bytes[13] {
+            0 => ServeOrder::Loser,
+            1 => ServeOrder::Alternate,
+            value => return Err(ConfigError::UnknownServeOrder { value }),
+        };
+        Ok(MatchRules {
+            points_to_win: u32_at(0) as i32,
+            win_by_two: bytes[4] != 0,
+            time_limit_frames: match u32_at(5) {
+                0 => None,
+                frames => Some(frames),
+            },
+            best_of: u32_at(9),
+            serve_order,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_toml() {
+        assert_eq!(GAME_CONFIG.validate(), Ok(()));
+        assert_eq!(PongGameConfig::from_toml(""), Ok(GAME_CONFIG));
+
+        // whatever is left out keeps its default
+        let config = PongGameConfig::from_toml(
+            "ball_speed = 600.0\narena_size = { x = 1200.0, y = 900.0 }\nmax_rollback_frames = 64",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            PongGameConfig {
+                ball_speed: 600.0,
+                arena_size: Vector2::new(1200.0, 900.0),
+                max_rollback_frames: 64,
+                ..GAME_CONFIG
+            }
+        );
+
+        // so do rules left out of the rules table
+        let config =
+            PongGameConfig::from_toml("[rules]\npoints_to_win = 5\ntime_limit_frames = 3600")
+                .unwrap();
+        assert_eq!(
+            config.rules,
+            MatchRules {
+                points_to_win: 5,
+                time_limit_frames: Some(3600),
+                ..GAME_CONFIG.rules
+            }
+        );
+
+        // typos and the wrong types aren't quietly ignored
+        assert!(matches!(
+            PongGameConfig::from_toml("bal_speed = 600.0"),
+            Err(ConfigError::Unreadable(_))
+        ));
+        assert!(matches!(
+            PongGameConfig::from_toml("[rules]\npoints_to_wn = 5"),
+            Err(ConfigError::Unreadable(_))
+        ));
+    }
+
+    #[test]
+    fn test_serve_order_from_toml() {
+        assert_eq!(GAME_CONFIG.rules.serve_order, ServeOrder::Loser);
+        let serve_order =
+            |text: &str| PongGameConfig::from_toml(text).map(|config| config.rules.serve_order);
+        assert_eq!(
+            serve_order("[rules]\nserve_order = \"alternate\""),
+            Ok(ServeOrder::Alternate)
+        );
+        assert_eq!(
+            serve_order("[rules]\nserve_order = \"loser\""),
+            Ok(ServeOrder::Loser)
+        );
+        assert!(matches!(
+            serve_order("[rules]\nserve_order = \"winner\""),
+            Err(ConfigError::Unreadable(_))
+        ));
+        assert!(matches!(
+            PongGameConfig::from_toml("ball_speed = \"fast\""),
+            Err(ConfigError::Unreadable(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let check = |text: &str| PongGameConfig::from_toml(text).err();
+        assert_eq!(
+            check("max_rollback_frames = 512"),
+            Some(ConfigError::RollbackFramesOutOfRange { frames: 512 })
+        );
+        assert_eq!(
+            check("max_rollback_frames = 2"),
+            Some(ConfigError::RollbackFramesOutOfRange { frames: 2 })
+        );
+        assert_eq!(
+            check("max_rollback_frames = 100"),
+            Some(ConfigError::RollbackFramesNotPowerOfTwo { frames: 100 })
+        );
+        assert_eq!(check("max_rollback_frames = 128"), None);
+        assert_eq!(
+            check("paddle_size = { x = 25.0, y = -1.0 }"),
+            Some(ConfigError::NotPositive {
+                field: "paddle_size.y"
+            })
+        );
+        assert_eq!(
+            check("dt = 0.0"),
+            Some(ConfigError::NotPositive { field: "dt" })
+        );
+        assert_eq!(
+            check("ball_speed = nan"),
+            Some(ConfigError::NotFinite {
+                field: "ball_speed"
+            })
+        );
+        assert_eq!(
+            check("paddle_friction = -1.0"),
+            Some(ConfigError::Negative {
+                field: "paddle_friction"
+            })
+        );
+        assert_eq!(
+            check("max_bounce_angle = 1.6"),
+            Some(ConfigError::BounceAngleOutOfRange { angle: 1.6 })
+        );
+        assert_eq!(
+            check("paddle_size = { x = 25.0, y = 800.0 }"),
+            Some(ConfigError::DoesntFit { what: "a paddle" })
+        );
+        assert_eq!(check("paddle_spin = -0.001\nserve_spread = 0.0"), None);
+        assert_eq!(
+            check("[rules]\npoints_to_win = 0"),
+            Some(ConfigError::NotPositive {
+                field: "rules.points_to_win"
+            })
+        );
+        assert_eq!(
+            check("[rules]\ntime_limit_frames = 0"),
+            Some(ConfigError::NotPositive {
+                field: "rules.time_limit_frames"
+            })
+        );
+        assert_eq!(
+            check("[rules]\nbest_of = 0"),
+            Some(ConfigError::NotPositive {
+                field: "rules.best_of"
+            })
+        );
+    }
+
+    #[test]
+    fn test_encode_and_hash() {
+        let config = PongGameConfig {
+            score_font_size: 60,
+            max_rollback_frames: 256,
+            serve_hold_frames: 90,
+            paddle_spin: -0.25,
+            rules: MatchRules {
+                points_to_win: 21,
+                win_by_two: false,
+                time_limit_frames: Some(3600),
+                best_of: 3,
+                serve_order: ServeOrder::Alternate,
+            },
+            ..GAME_CONFIG
+        };
+        assert_eq!(PongGameConfig::decode(&config.encode()), Ok(config));
+        assert_eq!(
+            PongGameConfig::decode(&GAME_CONFIG.encode()),
+            Ok(GAME_CONFIG)
+        );
+
+        // the same settings always hash the same, any change shows up
+        assert_eq!(
+            PongGameConfig::decode(&config.encode())
+                .unwrap()
+                .config_hash(),
+            config.config_hash()
+        );
+        assert_ne!(config.config_hash(), GAME_CONFIG.config_hash());
+        let nudged = PongGameConfig {
+            dt: GAME_CONFIG.dt * 1.0001,
+            ..GAME_CONFIG
+        };
+        assert_ne!(nudged.config_hash(), GAME_CONFIG.config_hash());
+        let longer = PongGameConfig {
+            rules: MatchRules {
+                points_to_win: 12,
+                ..GAME_CONFIG.rules
+            },
+            ..GAME_CONFIG
+        };
+        assert_ne!(longer.config_hash(), GAME_CONFIG.config_hash());
+
+        let mut bytes = GAME_CONFIG.encode();
+        bytes[ENCODED_CONFIG_SIZE - 1] = 7;
+        assert_eq!(
+            PongGameConfig::decode(&bytes),
+            Err(ConfigError::UnknownServeOrder { value: 7 })
+        );
+    }
+}
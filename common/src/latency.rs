@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant};
+
+// how often each side of a connection pings the other
+pub const PING_INTERVAL: Duration = Duration::from_millis(500);
+// a connection that hasn't sent anything in this long is treated as dead
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Smoothed round trip time and jitter, estimated the same way TCP does it (RFC 6298)
+#[derive(Clone, Debug, Default)]
+pub struct RttEstimator {
+    smoothed: Option<Duration>,
+    variation: Duration,
+    pub samples: u64,
+}
+
+impl RttEstimator {
+    pub fn new() -> Self {
+        RttEstimator::default()
+    }
+
+    pub fn add_sample(&mut self, rtt: Duration) {
+        self.samples += 1;
+        match self.smoothed {
+            None => {
+                self.smoothed = Some(rtt);
+                self.variation = rtt / 2;
+            }
+            Some(smoothed) => {
+                let difference = rtt.abs_diff(smoothed);
+                self.variation = (self.variation * 3 + difference) / 4;
+                self.smoothed = Some((smoothed * 7 + rtt) / 8);
+            }
+        }
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        self.smoothed
+    }
+
+    /// How much the round trip time varies from one sample to the next
+    pub fn jitter(&self) -> Option<Duration> {
+        self.smoothed.map(|_| self.variation)
+    }
+
+    /// How many frames of input delay would hide the one way latency, with some
+    /// room for jitter so an unlucky packet doesn't force a rollback
+    pub fn input_delay_frames(&self, frame_time: Duration) -> u32 {
+        match self.smoothed {
+            Some(smoothed) => {
+                let to_hide = smoothed / 2 + self.variation * 2;
+                (to_hide.as_secs_f64() / frame_time.as_secs_f64()).ceil() as u32
+            }
+            None => 0,
+        }
+    }
+}
+
+/// Decides when to ping the other end of a connection, turns the pongs that come
+/// back into round trip time samples, and notices when the other end goes quiet
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    // pings carry the time they were sent relative to this, so only this side has to make sense of it
+    epoch: Instant,
+    last_ping_sent: Option<Instant>,
+    last_heard: Instant,
+    pub rtt: RttEstimator,
+}
+
+impl Heartbeat {
+    pub fn new(now: Instant) -> Self {
+        Heartbeat {
+            epoch: now,
+            last_ping_sent: None,
+            last_heard: now,
+            rtt: RttEstimator::new(),
+        }
+    }
+
+    /// The `sent_at` to put in a ping if it's time to send one
+    pub fn ping_due(&mut self, now: Instant) -> Option<u64> {
+        let due = match self.last_ping_sent {
+            Some(last_ping_sent) => now.duration_since(last_ping_sent) >= PING_INTERVAL,
+            None => true,
+        };
+        if !due {
+            return None;
+        }
+        self.last_ping_sent = Some(now);
+        Some(now.duration_since(self.epoch).as_micros() as u64)
+    }
+
+    /// Anything at all arriving means the other end is still alive
+    pub fn heard_from(&mut self, now: Instant) {
+        self.last_heard = now;
+    }
+
+    /// Returns the round trip time the pong measured
+    pub fn pong_received(&mut self, sent_at: u64, now: Instant) -> Duration {
+        self.heard_from(now);
+        let sent_at = self.epoch + Duration::from_micros(sent_at);
+        let rtt = now.saturating_duration_since(sent_at);
+        self.rtt.add_sample(rtt);
+        rtt
+    }
+
+    pub fn quiet_for(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_heard)
+    }
+
+    pub fn timed_out(&self, now: Instant) -> bool {
+        self.quiet_for(now) >= HEARTBEAT_TIMEOUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtt_estimator() {
+        let mut estimator = RttEstimator::new();
+        assert_eq!(estimator.rtt(), None);
+        assert_eq!(estimator.input_delay_frames(Duration::from_millis(16)), 0);
+
+        for _ in 0..100 {
+            estimator.add_sample(Duration::from_millis(90));
+        }
+        let rtt = estimator.rtt().unwrap();
+        assert!(rtt >= Duration::from_millis(89) && rtt <= Duration::from_millis(91));
+        assert!(estimator.jitter().unwrap() < Duration::from_millis(1));
+        // 45ms one way is a bit under 3 frames at 60fps
+        assert_eq!(estimator.input_delay_frames(Duration::from_secs(1) / 60), 3);
+
+        estimator.add_sample(Duration::from_millis(300));
+        assert!(estimator.jitter().unwrap() >= Duration::from_millis(49));
+        assert!(estimator.rtt().unwrap() > rtt);
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(start);
+        let sent_at = heartbeat.ping_due(start).unwrap();
+        assert_eq!(heartbeat.ping_due(start + PING_INTERVAL / 2), None);
+        assert!(heartbeat.ping_due(start + PING_INTERVAL).is_some());
+
+        let rtt = heartbeat.pong_received(sent_at, start + Duration::from_millis(40));
+        assert_eq!(rtt, Duration::from_millis(40));
+        assert_eq!(heartbeat.rtt.samples, 1);
+
+        assert!(!heartbeat.timed_out(start + HEARTBEAT_TIMEOUT));
+        assert!(heartbeat.timed_out(start + Duration::from_millis(40) + HEARTBEAT_TIMEOUT));
+    }
+}
@@ -1,9 +1,27 @@
 use std::mem::size_of;
 
+pub mod config; // loading, checking and agreeing on the game settings
+pub mod latency; // heartbeats and round trip time estimates
+pub mod protocol; // messages sent to and from the relay after the lobby handshake
+pub mod replay; // recorded matches, saved by the relay and the game
+pub mod rng; // deterministic random numbers for the simulation
+pub mod sim; // the game simulation, run by both players and an authoritative relay
+pub mod validation; // checks on inputs coming from the other end of a connection
+
 pub const PORT: u32 = 5321;
 pub const DEVEL_IP: &str = "localhost:5321";
 pub const PROD_IP: &str = "143.198.74.108:5321";
 
+/// FNV-1a, for cheap fingerprints that come out the same on every machine
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes.iter() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct PongInputState {
@@ -11,13 +29,21 @@ pub struct PongInputState {
     // unitialized memory to be transmitted over the network: BAD IDEA - Ben Aubin
     pub frame: u32,
     pub input: f32,
+    // which of the BUTTON_ bits are held down. A u32 so the struct stays without padding
+    pub buttons: u32,
 }
 
 impl PongInputState {
-    pub fn new() -> Self {
+    /// Launches the ball while serving
+    pub const BUTTON_SERVE: u32 = 1 << 0;
+    pub const ALL_BUTTONS: u32 = PongInputState::BUTTON_SERVE;
+
+    /// No buttons held
+    pub fn new(frame: u32, input: f32) -> Self {
         PongInputState {
-            frame: 0,
-            input: 0.0,
+            frame,
+            input,
+            buttons: 0,
         }
     }
 
@@ -26,11 +52,16 @@ impl PongInputState {
         PongInputState {
             frame: 0,
             input: input,
+            buttons: 0,
         }
     }
 
+    pub fn pressed(&self, button: u32) -> bool {
+        self.buttons & button != 0
+    }
+
     // To ensure byte alignment, you should probably
-    // call this like PongInputState::new().into_u8()
+    // call this like PongInputState::new(0, 0.0).into_u8()
     pub fn into_u8(self) -> [u8; size_of::<Self>()] {
         unsafe { std::mem::transmute(self) }
     }
@@ -0,0 +1,538 @@
+// Everything sent over a client <-> relay connection after the lobby handshake is a
+// message: one tag byte followed by a payload whose size is fixed by the tag.
+
+use std::io;
+use std::io::{Read, Write};
+use std::mem::size_of;
+use std::net::TcpStream;
+
+use crate::config::ENCODED_CONFIG_SIZE;
+use crate::sim::PongGameConfig;
+use crate::PongInputState;
+
+// bumped whenever a message changes meaning or layout, replays record which one they were made with
+pub const PROTOCOL_VERSION: u32 = 6;
+
+// lobby handshake, the client sends [command, i32 lobby code] and the relay answers with an i32
+pub const CREATE_LOBBY_COMMAND: u8 = 1;
+pub const JOIN_LOBBY_COMMAND: u8 = 2;
+pub const RECONNECT_COMMAND: u8 = 3; // followed by the rest of the ReconnectRequest
+pub const SPECTATE_COMMAND: u8 = 4;
+pub const LOBBY_CREATE_FAILED: i32 = 0; // sent instead of a lobby code
+pub const JOIN_OK: i32 = 200;
+pub const RECONNECT_OK: i32 = 200;
+pub const SPECTATE_OK: i32 = 200;
+pub const JOIN_NO_SUCH_LOBBY: i32 = 400;
+pub const RECONNECT_REJECTED: i32 = 401; // no running match in that lobby with that session token
+pub const SPECTATE_NO_SUCH_MATCH: i32 = 404;
+pub const JOIN_SERVER_SHUTTING_DOWN: i32 = 503;
+
+const OPPONENT_JOINED_TAG: u8 = 1;
+const INPUT_TAG: u8 = 2;
+const SERVER_SHUTTING_DOWN_TAG: u8 = 3;
+const SESSION_TOKEN_TAG: u8 = 4;
+const OPPONENT_DISCONNECTED_TAG: u8 = 5;
+const OPPONENT_RECONNECTED_TAG: u8 = 6;
+const OPPONENT_LEFT_TAG: u8 = 7;
+const RESUMED_TAG: u8 = 8;
+const PING_TAG: u8 = 9;
+const PONG_TAG: u8 = 10;
+const PEER_PING_TAG: u8 = 11;
+const PEER_PONG_TAG: u8 = 12;
+const PLAYER_INPUT_TAG: u8 = 13;
+const SNAPSHOT_TAG: u8 = 14;
+const MATCH_RESULT_TAG: u8 = 15;
+const MATCH_SEED_TAG: u8 = 16;
+const MATCH_CONFIG_TAG: u8 = 17;
+const CONFIG_HASH_TAG: u8 = 18;
+
+// a serialized PongGameState, see sim::PongGameState::to_bytes. Paddles are 3 f32s and
+// a bool, the ball 5 f32s, scores an i32 and a bool, then the frames into the game and games
+// won as u32s, the match phase and winner as a byte each, and the server and serve phase as a
+// byte each with the frames left on the serve as a u32, then the random number generator's u64
+pub const SNAPSHOT_STATE_SIZE: usize = 2 * 13 + 20 + 2 * 5 + 3 * 4 + 2 + 2 + 4 + 8;
+
+/// Sent instead of a create or join command by a player whose connection dropped mid match
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ReconnectRequest {
+    pub lobby_code: i32,
+    pub session_token: u64,
+    // the first opponent input the player hasn't received, the relay resends everything from there
+    pub next_frame_expected: u32,
+}
+
+impl ReconnectRequest {
+    // the command byte and lobby code, then the token and frame
+    pub const SIZE: usize = 5 + 8 + 4;
+
+    pub fn encode(&self) -> [u8; ReconnectRequest::SIZE] {
+        let mut out = [0u8; ReconnectRequest::SIZE];
+        out[0] = RECONNECT_COMMAND;
+        out[1..5].copy_from_slice(&self.lobby_code.to_le_bytes());
+        out[5..13].copy_from_slice(&self.session_token.to_le_bytes());
+        out[13..17].copy_from_slice(&self.next_frame_expected.to_le_bytes());
+        out
+    }
+
+    /// The relay reads the usual 5 byte command first, then the remaining bytes
+    pub fn decode(command: &[u8; 5], rest: &[u8; ReconnectRequest::SIZE - 5]) -> Self {
+        let mut token_bytes = [0u8; 8];
+        token_bytes.copy_from_slice(&rest[0..8]);
+        ReconnectRequest {
+            lobby_code: i32::from_le_bytes([command[1], command[2], command[3], command[4]]),
+            session_token: u64::from_le_bytes(token_bytes),
+            next_frame_expected: u32::from_le_bytes([rest[8], rest[9], rest[10], rest[11]]),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Message {
+    /// relay -> host, somebody joined the lobby and the match starts now
+    OpponentJoined,
+    /// player -> relay -> other player
+    Input(PongInputState),
+    /// relay -> everybody, the relay is going away and will close the connection
+    /// in `seconds_left` seconds whether or not the match is over
+    ServerShuttingDown { seconds_left: u32 },
+    /// relay -> player when the match starts, needed to reconnect if the connection drops
+    SessionToken { token: u64 },
+    /// relay -> player, the match is kept alive for `grace_seconds` waiting for the opponent to reconnect
+    OpponentDisconnected { grace_seconds: u32 },
+    /// relay -> player, the opponent is back and their inputs will start flowing again
+    OpponentReconnected,
+    /// relay -> player, the opponent didn't reconnect in time and the match is over
+    OpponentLeft,
+    /// relay -> reconnected player, the first of the player's own inputs the relay
+    /// doesn't have. Everything from that frame on needs to be sent again
+    Resumed { next_frame_expected: u32 },
+    /// relay <-> player heartbeat. `sent_at` is only meaningful to the sender, who gets
+    /// it back in the Pong and uses it to measure the round trip time
+    Ping { sent_at: u64 },
+    /// answer to a Ping, echoing its `sent_at`
+    Pong { sent_at: u64 },
+    /// player -> relay -> other player, like Ping but measured all the way to the opponent
+    PeerPing { sent_at: u64 },
+    /// answer to a PeerPing, relayed back to the player who sent it
+    PeerPong { sent_at: u64 },
+    /// relay -> spectator, an input from the player on `side`, 0 is the host on the left
+    PlayerInput { side: u8, input: PongInputState },
+    /// player -> relay -> late joining spectator, the game state after `frame` was simulated
+    /// with both players' real inputs. Spectators start from it instead of from frame 0
+    Snapshot {
+        frame: u32,
+        state: [u8; SNAPSHOT_STATE_SIZE],
+    },
+    /// authoritative relay -> everybody, the official score from the relay's own simulation,
+    /// sent when the match ends. `frame` is the last frame it simulated
+    MatchResult {
+        frame: u32,
+        left_score: i32,
+        right_score: i32,
+    },
+    /// relay -> players and spectators when the match starts, what the simulation's random
+    /// numbers are seeded with. Nobody simulates a frame before they have it
+    MatchSeed { seed: u64 },
+    /// host -> relay -> joiner and spectators, the settings the match is played with. The
+    /// relay sends it again to a joiner who reconnects and to spectators who join late
+    MatchConfig { config: PongGameConfig },
+    /// joiner -> relay -> host, the `config_hash` of the MatchConfig the joiner got. The host
+    /// doesn't simulate a frame before it has one that matches its own
+    ConfigHash { hash: u64 },
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(1 + size_of::<PongInputState>());
+        match self {
+            Message::OpponentJoined => out.push(OPPONENT_JOINED_TAG),
+            Message::Input(input) => {
+                out.push(INPUT_TAG);
+                out.extend_from_slice(&input.into_u8());
+            }
+            Message::ServerShuttingDown { seconds_left } => {
+                out.push(SERVER_SHUTTING_DOWN_TAG);
+                out.extend_from_slice(&seconds_left.to_le_bytes());
+            }
+            Message::SessionToken { token } => {
+                out.push(SESSION_TOKEN_TAG);
+                out.extend_from_slice(&token.to_le_bytes());
+            }
+            Message::OpponentDisconnected { grace_seconds } => {
+                out.push(OPPONENT_DISCONNECTED_TAG);
+                out.extend_from_slice(&grace_seconds.to_le_bytes());
+            }
+            Message::OpponentReconnected => out.push(OPPONENT_RECONNECTED_TAG),
+            Message::OpponentLeft => out.push(OPPONENT_LEFT_TAG),
+            Message::Resumed {
+                next_frame_expected,
+            } => {
+                out.push(RESUMED_TAG);
+                out.extend_from_slice(&next_frame_expected.to_le_bytes());
+            }
+            Message::Ping { sent_at } => {
+                out.push(PING_TAG);
+                out.extend_from_slice(&sent_at.to_le_bytes());
+            }
+            Message::Pong { sent_at } => {
+                out.push(PONG_TAG);
+                out.extend_from_slice(&sent_at.to_le_bytes());
+            }
+            Message::PeerPing { sent_at } => {
+                out.push(PEER_PING_TAG);
+                out.extend_from_slice(&sent_at.to_le_bytes());
+            }
+            Message::PeerPong { sent_at } => {
+                out.push(PEER_PONG_TAG);
+                out.extend_from_slice(&sent_at.to_le_bytes());
+            }
+            Message::PlayerInput { side, input } => {
+                out.push(PLAYER_INPUT_TAG);
+                out.push(*side);
+                out.extend_from_slice(&input.into_u8());
+            }
+            Message::Snapshot { frame, state } => {
+                out.push(SNAPSHOT_TAG);
+                out.extend_from_slice(&frame.to_le_bytes());
+                out.extend_from_slice(state);
+            }
+            Message::MatchResult {
+                frame,
+                left_score,
+                right_score,
+            } => {
+                out.push(MATCH_RESULT_TAG);
+                out.extend_from_slice(&frame.to_le_bytes());
+                out.extend_from_slice(&left_score.to_le_bytes());
+                out.extend_from_slice(&right_score.to_le_bytes());
+            }
+            Message::MatchSeed { seed } => {
+                out.push(MATCH_SEED_TAG);
+                out.extend_from_slice(&seed.to_le_bytes());
+            }
+            Message::MatchConfig { config } => {
+                out.push(MATCH_CONFIG_TAG);
+                out.extend_from_slice(&config.encode());
+            }
+            Message::ConfigHash { hash } => {
+                out.push(CONFIG_HASH_TAG);
+                out.extend_from_slice(&hash.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    /// Decodes the message at the start of `bytes`, returning it and how many bytes it took up.
+    /// Ok(None) means the message hasn't fully arrived yet
+    pub fn decode(bytes: &[u8]) -> Result<Option<(Message, usize)>, io::Error> {
+        let tag = match bytes.first() {
+            Some(tag) => *tag,
+            None => return Ok(None),
+        };
+        let payload = &bytes[1..];
+        let payload_size = match tag {
+            OPPONENT_JOINED_TAG => 0,
+            INPUT_TAG => size_of::<PongInputState>(),
+            SERVER_SHUTTING_DOWN_TAG => size_of::<u32>(),
+            SESSION_TOKEN_TAG => size_of::<u64>(),
+            OPPONENT_DISCONNECTED_TAG => size_of::<u32>(),
+            OPPONENT_RECONNECTED_TAG => 0,
+            OPPONENT_LEFT_TAG => 0,
+            RESUMED_TAG => size_of::<u32>(),
+            PING_TAG | PONG_TAG | PEER_PING_TAG | PEER_PONG_TAG => size_of::<u64>(),
+            PLAYER_INPUT_TAG => 1 + size_of::<PongInputState>(),
+            SNAPSHOT_TAG => size_of::<u32>() + SNAPSHOT_STATE_SIZE,
+            MATCH_RESULT_TAG => 3 * size_of::<u32>(),
+            MATCH_SEED_TAG => size_of::<u64>(),
+            MATCH_CONFIG_TAG => ENCODED_CONFIG_SIZE,
+            CONFIG_HASH_TAG => size_of::<u64>(),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown message tag {}", tag),
+                ))
+            }
+        };
+        if payload.len() < payload_size {
+            return Ok(None);
+        }
+
+        let message = match tag {
+            OPPONENT_JOINED_TAG => Message::OpponentJoined,
+            INPUT_TAG => Message::Input(read_input(payload)),
+            SERVER_SHUTTING_DOWN_TAG => Message::ServerShuttingDown {
+                seconds_left: read_u32(payload),
+            },
+            SESSION_TOKEN_TAG => Message::SessionToken {
+                token: read_u64(payload),
+            },
+            OPPONENT_DISCONNECTED_TAG => Message::OpponentDisconnected {
+                grace_seconds: read_u32(payload),
+            },
+            OPPONENT_RECONNECTED_TAG => Message::OpponentReconnected,
+            OPPONENT_LEFT_TAG => Message::OpponentLeft,
+            RESUMED_TAG => Message::Resumed {
+                next_frame_expected: read_u32(payload),
+            },
+            PING_TAG => Message::Ping {
+                sent_at: read_u64(payload),
+            },
+            PONG_TAG => Message::Pong {
+                sent_at: read_u64(payload),
+            },
+            PEER_PING_TAG => Message::PeerPing {
+                sent_at: read_u64(payload),
+            },
+            PEER_PONG_TAG => Message::PeerPong {
+                sent_at: read_u64(payload),
+            },
+            PLAYER_INPUT_TAG => Message::PlayerInput {
+                side: payload[0],
+                input: read_input(&payload[1..]),
+            },
+            SNAPSHOT_TAG => {
+                let mut state = [0u8; SNAPSHOT_STATE_SIZE];
+                state.copy_from_slice(&payload[4..payload_size]);
+                Message::Snapshot {
+                    frame: read_u32(payload),
+                    state,
+                }
+            }
+            MATCH_RESULT_TAG => Message::MatchResult {
+                frame: read_u32(payload),
+                left_score: read_u32(&payload[4..]) as i32,
+                right_score: read_u32(&payload[8..]) as i32,
+            },
+            MATCH_SEED_TAG => Message::MatchSeed {
+                seed: read_u64(payload),
+            },
+            MATCH_CONFIG_TAG => {
+                let mut config = [0u8; ENCODED_CONFIG_SIZE];
+                config.copy_from_slice(&payload[..payload_size]);
+                let config = PongGameConfig::decode(&config)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                Message::MatchConfig { config }
+            }
+            CONFIG_HASH_TAG => Message::ConfigHash {
+                hash: read_u64(payload),
+            },
+            _ => unreachable!(),
+        };
+        Ok(Some((message, 1 + payload_size)))
+    }
+}
+
+fn read_input(bytes: &[u8]) -> PongInputState {
+    let mut input_bytes = [0u8; size_of::<PongInputState>()];
+    input_bytes.copy_from_slice(&bytes[..size_of::<PongInputState>()]);
+    unsafe { PongInputState::from_u8(input_bytes) }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut u64_bytes = [0u8; 8];
+    u64_bytes.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(u64_bytes)
+}
+
+/// A nonblocking stream of messages. Partially received messages and partially
+/// sent ones are buffered, so a slow socket never splits a message in half
+pub struct Connection {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> Result<Self, io::Error> {
+        stream.set_nonblocking(true)?;
+        Ok(Connection {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Queues the message and sends as much of the queue as the socket takes right now
+    pub fn send(&mut self, message: &Message) -> Result<(), io::Error> {
+        self.outgoing.extend_from_slice(&message.encode());
+        self.flush()
+    }
+
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(e) => match e.kind() {
+                    io::ErrorKind::WouldBlock => break,
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(e),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Buffers everything the socket has for us without blocking. Messages that arrived
+    /// before an error are still buffered and can be taken out with next_message
+    pub fn read_available(&mut self) -> Result<(), io::Error> {
+        let mut chunk = [0u8; 512];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(size) => self.incoming.extend_from_slice(&chunk[..size]),
+                Err(e) => match e.kind() {
+                    io::ErrorKind::WouldBlock => return Ok(()),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(e),
+                },
+            }
+        }
+    }
+
+    /// Takes the next fully buffered message, leaving the rest for later
+    pub fn next_message(&mut self) -> Result<Option<Message>, io::Error> {
+        match Message::decode(&self.incoming)? {
+            Some((message, size)) => {
+                self.incoming.drain(..size);
+                Ok(Some(message))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns every message that has fully arrived. If the connection failed, the
+    /// error comes back after the messages that made it through before the failure
+    pub fn receive(&mut self) -> (Vec<Message>, Option<io::Error>) {
+        let mut error = self.read_available().err();
+
+        let mut messages = Vec::new();
+        loop {
+            match self.next_message() {
+                Ok(Some(message)) => messages.push(message),
+                Ok(None) => break,
+                Err(e) => {
+                    // can't find the start of the next message after garbage, so give up on the stream
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        (messages, error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::GAME_CONFIG;
+
+    #[test]
+    fn test_roundtrip() {
+        let messages = [
+            Message::OpponentJoined,
+            Message::Input(PongInputState {
+                frame: 1234,
+                input: -0.5,
+                buttons: PongInputState::BUTTON_SERVE,
+            }),
+            Message::ServerShuttingDown { seconds_left: 30 },
+            Message::SessionToken {
+                token: 0xdead_beef_1234_5678,
+            },
+            Message::OpponentDisconnected { grace_seconds: 15 },
+            Message::OpponentReconnected,
+            Message::OpponentLeft,
+            Message::Resumed {
+                next_frame_expected: 77,
+            },
+            Message::Ping { sent_at: 1 },
+            Message::Pong { sent_at: 2 },
+            Message::PeerPing { sent_at: 3 },
+            Message::PeerPong { sent_at: u64::MAX },
+            Message::PlayerInput {
+                side: 1,
+                input: PongInputState {
+                    frame: 99,
+                    input: 1.0,
+                    buttons: 0,
+                },
+            },
+            Message::Snapshot {
+                frame: 600,
+                state: [7; SNAPSHOT_STATE_SIZE],
+            },
+            Message::MatchResult {
+                frame: 3600,
+                left_score: 5,
+                right_score: -1,
+            },
+            Message::MatchSeed {
+                seed: 0x0123_4567_89ab_cdef,
+            },
+            Message::MatchConfig {
+                config: PongGameConfig {
+                    paddle_spin: 0.0,
+                    ..GAME_CONFIG
+                },
+            },
+            Message::ConfigHash {
+                hash: GAME_CONFIG.config_hash(),
+            },
+        ];
+        let mut bytes = Vec::new();
+        for message in messages.iter() {
+            bytes.extend_from_slice(&message.encode());
+        }
+
+        let mut decoded = Vec::new();
+        let mut cursor = 0;
+        while let Some((message, size)) = Message::decode(&bytes[cursor..]).unwrap() {
+            decoded.push(message);
+            cursor += size;
+        }
+        assert_eq!(cursor, bytes.len());
+        assert_eq!(&decoded[..], &messages[..]);
+    }
+
+    #[test]
+    fn test_partial_message() {
+        let bytes = Message::ServerShuttingDown { seconds_left: 5 }.encode();
+        for partial_size in 0..bytes.len() {
+            assert!(Message::decode(&bytes[..partial_size]).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_reconnect_request_roundtrip() {
+        let request = ReconnectRequest {
+            lobby_code: -42,
+            session_token: u64::MAX - 3,
+            next_frame_expected: 600,
+        };
+        let bytes = request.encode();
+        let mut command = [0u8; 5];
+        command.copy_from_slice(&bytes[..5]);
+        let mut rest = [0u8; ReconnectRequest::SIZE - 5];
+        rest.copy_from_slice(&bytes[5..]);
+
+        assert_eq!(command[0], RECONNECT_COMMAND);
+        assert_eq!(ReconnectRequest::decode(&command, &rest), request);
+    }
+
+    #[test]
+    fn test_unknown_tag() {
+        assert!(Message::decode(&[255, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+// A recorded match, written by the relay or the game and played back by the game's replay
+// viewer. Only inputs are stored, the simulation is deterministic so playing them through a
+// fresh PongGameState gives back the same match.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::config::ENCODED_CONFIG_SIZE;
+use crate::protocol::PROTOCOL_VERSION;
+use crate::sim::{PongGameConfig, PongGameState};
+use crate::PongInputState;
+
+const MAGIC: &[u8; 8] = b"PONGRPLY";
+pub const FORMAT_VERSION: u32 = 7;
+pub const FILE_EXTENSION: &str = "pongreplay";
+
+/// For the timestamps in a replay
+pub fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Pulls values back out of an encoded replay in the order they were written
+struct Reader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> io::Result<&[u8]> {
+        if self.bytes.len() - self.cursor < len {
+            return Err(invalid(String::from("replay is cut short")));
+        }
+        self.cursor += len;
+        Ok(&self.bytes[self.cursor - len..self.cursor])
+    }
+    fn read_4(&mut self) -> io::Result<[u8; 4]> {
+        let mut out = [0u8; 4];
+        out.copy_from_slice(self.take(4)?);
+        Ok(out)
+    }
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.read_4()?))
+    }
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.read_4()?))
+    }
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.read_4()?))
+    }
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut out = [0u8; 8];
+        out.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(out))
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Replay {
+    pub lobby_code: i32,
+    // what the relay seeded the match's random numbers with
+    pub seed: u64,
+    // the host's game settings, the match was played with them
+    pub config: PongGameConfig,
+    // milliseconds since the unix epoch
+    pub started_at: u64,
+    pub ended_at: u64,
+    // why the match ended, as the recorder saw it
+    pub end_reason: String,
+    // one input per frame starting from frame 0, 0 is the host on the left. A player who was
+    // ahead when the match ended has more of them than their opponent. Frame numbers aren't
+    // saved, they come from where the input is in the list
+    pub inputs: [Vec<PongInputState>; 2],
+}
+
+impl Replay {
+    /// How many frames can be played back, the ones both players' inputs are in for
+    pub fn frames(&self) -> u32 {
+        self.inputs[0].len().min(self.inputs[1].len()) as u32
+    }
+
+    /// The state the match started from, before frame 0
+    pub fn initial_state(&self) -> PongGameState {
+        PongGameState::with_config(self.config, self.seed)
+    }
+
+    pub fn inputs_for(&self, frame: u32) -> [PongInputState; 2] {
+        [0, 1].map(|side| PongInputState {
+            frame,
+            ..self.inputs[side][frame as usize]
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.config.encode());
+        out.extend_from_slice(&self.lobby_code.to_le_bytes());
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.started_at.to_le_bytes());
+        out.extend_from_slice(&self.ended_at.to_le_bytes());
+        out.extend_from_slice(&(self.end_reason.len() as u32).to_le_bytes());
+        out.extend_from_slice(self.end_reason.as_bytes());
+        for inputs in self.inputs.iter() {
+            out.extend_from_slice(&(inputs.len() as u32).to_le_bytes());
+            for input in inputs.iter() {
+                out.extend_from_slice(&input.input.to_le_bytes());
+                out.extend_from_slice(&input.buttons.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    /// Writes the replay into `dir`, named after when the match started and its lobby
+    pub fn save_in(&self, dir: &Path) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "{}-{}.{}",
+            self.started_at, self.lobby_code, FILE_EXTENSION
+        ));
+        fs::write(&path, self.encode())?;
+        Ok(path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Replay> {
+        Replay::decode(&fs::read(path)?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Replay> {
+        let mut reader = Reader { bytes, cursor: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid(String::from("not a replay file")));
+        }
+        let format_version = reader.u32()?;
+        if format_version != FORMAT_VERSION {
+            return Err(invalid(format!(
+                "replay format version {} isn't supported, expected {}",
+                format_version, FORMAT_VERSION
+            )));
+        }
+        // inputs mean the same thing in every protocol version so far, it's only kept for reference
+        let _protocol_version = reader.u32()?;
+        let mut config = [0u8; ENCODED_CONFIG_SIZE];
+        config.copy_from_slice(reader.take(ENCODED_CONFIG_SIZE)?);
+        let config = PongGameConfig::decode(&config)
+            .and_then(|config| config.validate().map(|()| config))
+            .map_err(|e| invalid(format!("replay's game settings are unusable: {}", e)))?;
+
+        let lobby_code = reader.i32()?;
+        let seed = reader.u64()?;
+        let started_at = reader.u64()?;
+        let ended_at = reader.u64()?;
+        let reason_len = reader.u32()? as usize;
+        let end_reason = String::from_utf8(reader.take(reason_len)?.to_vec())
+            .map_err(|e| invalid(e.to_string()))?;
+        let mut inputs = [Vec::new(), Vec::new()];
+        for side_inputs in inputs.iter_mut() {
+            let len = reader.u32()? as usize;
+            if (bytes.len() - reader.cursor) / 8 < len {
+                return Err(invalid(String::from("replay is cut short")));
+            }
+            side_inputs.reserve(len);
+            for frame in 0..len {
+                side_inputs.push(PongInputState {
+                    frame: frame as u32,
+                    input: reader.f32()?,
+                    buttons: reader.u32()?,
+                });
+            }
+        }
+
+        Ok(Replay {
+            lobby_code,
+            seed,
+            config,
+            started_at,
+            ended_at,
+            end_reason,
+            inputs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{MatchRules, GAME_CONFIG};
+
+    fn inputs(values: &[f32]) -> Vec<PongInputState> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(frame, input)| PongInputState {
+                frame: frame as u32,
+                input: *input,
+                buttons: 0,
+            })
+            .collect()
+    }
+
+    fn replay() -> Replay {
+        let mut inputs = [inputs(&[0.0, 1.0, -1.0, 0.5]), inputs(&[1.0, 1.0, 0.0])];
+        inputs[0][2].buttons = PongInputState::BUTTON_SERVE;
+        Replay {
+            lobby_code: 1234,
+            seed: 0xfeed,
+            config: PongGameConfig {
+                ball_speed: 500.0,
+                rules: MatchRules {
+                    points_to_win: 5,
+                    time_limit_frames: Some(3600),
+                    best_of: 3,
+                    ..GAME_CONFIG.rules
+                },
+                ..GAME_CONFIG
+            },
+            started_at: 1_700_000_000_000,
+            ended_at: 1_700_000_060_000,
+            end_reason: String::from("joiner didn't reconnect in time"),
+            inputs,
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let replay = replay();
+        assert_eq!(Replay::decode(&replay.encode()).unwrap(), replay);
+        assert_eq!(replay.initial_state().config.rules, replay.config.rules);
+        assert_eq!(replay.frames(), 3);
+        assert_eq!(
+            replay.inputs_for(2),
+            [
+                PongInputState {
+                    frame: 2,
+                    input: -1.0,
+                    buttons: PongInputState::BUTTON_SERVE,
+                },
+                PongInputState {
+                    frame: 2,
+                    input: 0.0,
+                    buttons: 0,
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_files() {
+        let bytes = replay().encode();
+        assert!(Replay::decode(b"not a replay").is_err());
+        assert!(Replay::decode(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut other_version = bytes.clone();
+        other_version[8] = 99;
+        assert!(Replay::decode(&other_version).is_err());
+
+        let mut bad_config = replay();
+        bad_config.config.dt = 0.0;
+        assert!(Replay::decode(&bad_config.encode()).is_err());
+
+        let mut bad_rules = replay();
+        bad_rules.config.rules.best_of = 0;
+        assert!(Replay::decode(&bad_rules.encode()).is_err());
+    }
+}
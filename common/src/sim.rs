@@ -0,0 +1,1472 @@
+// The pong simulation, shared by the game and the relay so both get exactly the same result from
+// the same inputs. Nothing in here draws or touches the network.
+
+use std::cmp::Ordering;
+use std::f32::consts::FRAC_PI_3;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, Sub};
+
+use serde::Deserialize;
+
+use crate::protocol::SNAPSHOT_STATE_SIZE;
+use crate::rng::Rng;
+use crate::{fnv1a, PongInputState};
+
+/// The settings a match plays with unless a config file says otherwise, see `config.rs`
+pub const GAME_CONFIG: PongGameConfig = PongGameConfig {
+    arena_size: Vector2::new(1000.0, 800.0),
+    paddle_size: Vector2::new(25.0, 175.0),
+    paddle_force: 1000.0,
+    paddle_friction: 300.0,
+    ball_size: 20.0,
+    ball_speed: 400.0,
+    score_font_size: 80,
+    max_rollback_frames: 128,
+    dt: 1.0 / 60.0,
+    serve_countdown_frames: 120,
+    serve_hold_frames: 180,
+    serve_spread: 0.5,
+    max_bounce_angle: FRAC_PI_3,
+    paddle_spin: 0.0004,
+    rules: MATCH_RULES,
+};
+
+/// The rules a match plays by unless a config file says otherwise
+pub const MATCH_RULES: MatchRules = MatchRules {
+    points_to_win: 11,
+    win_by_two: true,
+    time_limit_frames: None,
+    best_of: 1,
+    serve_order: ServeOrder::Loser,
+};
+
+/// Everything about how the game plays. Both players have to simulate with the same one, the
+/// host's, so it's sent to the joiner when the match starts
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PongGameConfig {
+    pub arena_size: Vector2,
+    pub paddle_size: Vector2,
+    pub paddle_force: f32,
+    pub paddle_friction: f32,
+    pub ball_size: f32,
+    pub ball_speed: f32,
+    pub score_font_size: i32,
+    pub max_rollback_frames: usize, // see PongGameConfig::validate for how many are allowed
+    pub dt: f32,
+    // frames between a point and the server being able to launch, then how long they can hold
+    // on to the ball before it goes by itself
+    pub serve_countdown_frames: u32,
+    pub serve_hold_frames: u32,
+    // how far up or down a serve can go at random, as a slope
+    pub serve_spread: f32,
+    // the steepest a ball can leave the front of a paddle, in radians from straight across.
+    // It goes out at this angle off the very ends, and straight across off the middle
+    pub max_bounce_angle: f32,
+    // radians added to that angle for every unit of paddle velocity, so a ball hit on the move
+    // goes the way the paddle was going. 0 for the angle to come from where it hit alone
+    pub paddle_spin: f32,
+    // how the match is won, the `[rules]` table in a config file
+    pub rules: MatchRules,
+}
+
+// for fields a config file leaves out
+impl Default for PongGameConfig {
+    fn default() -> Self {
+        GAME_CONFIG
+    }
+}
+
+impl PongGameConfig {
+    /// Whole seconds `frames` frames last, rounded up the way countdowns are shown
+    pub fn seconds_left(&self, frames: u32) -> u32 {
+        (frames as f32 * self.dt).ceil() as u32
+    }
+
+    /// How far past the last input we have from a player their next one can be. An honest
+    /// player waits a couple of frames short of max_rollback_frames past the opponent's last
+    /// input, this leaves room for the inputs the opponent has sent that they haven't seen yet
+    pub fn max_frames_ahead_of_opponent(&self) -> u32 {
+        self.max_rollback_frames as u32 * 2
+    }
+}
+
+/// How a match is won. Part of the game settings, so both players play by the host's
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchRules {
+    /// Points that win a game
+    pub points_to_win: i32,
+    /// A game isn't won until the winner is two points clear
+    pub win_by_two: bool,
+    /// Frames a game lasts before whoever is ahead wins it. A game that's tied by then goes to
+    /// sudden death overtime, where the next point wins
+    pub time_limit_frames: Option<u32>,
+    /// Games in the match, whoever wins more than half of them wins it
+    pub best_of: u32,
+    pub serve_order: ServeOrder,
+}
+
+// for fields a config file leaves out
+impl Default for MatchRules {
+    fn default() -> Self {
+        MATCH_RULES
+    }
+}
+
+impl MatchRules {
+    pub fn games_to_win(&self) -> u32 {
+        self.best_of / 2 + 1
+    }
+}
+
+/// Who serves after a point. The host always serves first
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServeOrder {
+    /// Whoever just lost the point
+    Loser,
+    /// Each player in turn
+    Alternate,
+}
+
+/// Where the current serve is up to
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ServePhase {
+    /// The ball sits on the server's paddle and can't be launched yet
+    Countdown {
+        frames_left: u32,
+    },
+    /// The server can launch the ball, it goes by itself when the time is up
+    Holding {
+        frames_left: u32,
+    },
+    InPlay,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum MatchPhase {
+    Playing,
+    /// The time ran out on a tied game, the next point wins it
+    Overtime,
+    /// Nothing moves anymore
+    MatchOver {
+        winner: usize,
+    },
+}
+
+/// Does its math the same way raylib's Vector2 does, so moving the simulation out of the
+/// game didn't change a single result
+#[derive(PartialEq, Debug, Clone, Copy, Default, Deserialize)]
+pub struct Vector2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Vector2 {
+    pub const fn new(x: f32, y: f32) -> Vector2 {
+        Vector2 { x, y }
+    }
+
+    pub fn length(&self) -> f32 {
+        ((self.x * self.x) + (self.y * self.y)).sqrt()
+    }
+
+    pub fn normalize(&mut self) {
+        *self /= self.length();
+    }
+
+    pub fn normalized(&self) -> Vector2 {
+        *self / self.length()
+    }
+
+    pub fn dot(&self, v: Vector2) -> f32 {
+        self.x * v.x + self.y * v.y
+    }
+}
+
+impl Add for Vector2 {
+    type Output = Vector2;
+    fn add(self, v: Vector2) -> Self {
+        Vector2::new(self.x + v.x, self.y + v.y)
+    }
+}
+
+impl AddAssign for Vector2 {
+    fn add_assign(&mut self, v: Vector2) {
+        *self = *self + v;
+    }
+}
+
+impl Sub for Vector2 {
+    type Output = Vector2;
+    fn sub(self, v: Vector2) -> Self {
+        Vector2::new(self.x - v.x, self.y - v.y)
+    }
+}
+
+impl Mul<f32> for Vector2 {
+    type Output = Vector2;
+    fn mul(self, value: f32) -> Self {
+        Vector2::new(self.x * value, self.y * value)
+    }
+}
+
+impl Div<f32> for Vector2 {
+    type Output = Vector2;
+    fn div(self, value: f32) -> Self {
+        Vector2::new(self.x / value, self.y / value)
+    }
+}
+
+impl DivAssign<f32> for Vector2 {
+    fn div_assign(&mut self, value: f32) {
+        *self = *self / value;
+    }
+}
+
+/// Something that happened while a frame was simulated, for sound, effects and stats to react to.
+/// Sides are indexes like the inputs, 0 is left and 1 is right
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SimEvent {
+    /// The ball came off a paddle going `speed` units per second
+    PaddleHit {
+        side: usize,
+        speed: f32,
+    },
+    /// The ball came off the top or bottom of the arena
+    WallBounce,
+    Goal {
+        scorer: usize,
+    },
+    /// The ball was put on the server's paddle after a goal or a game
+    Serve {
+        server: usize,
+    },
+    /// The serve countdown went down a second, to 0 when the server can launch
+    ServeCountdown {
+        seconds_left: u32,
+    },
+    /// The server launched the ball, or held on to it for too long
+    Launch {
+        server: usize,
+    },
+    /// Time ran out with the game tied
+    Overtime,
+    GameOver {
+        winner: usize,
+    },
+    MatchOver {
+        winner: usize,
+    },
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Paddle {
+    pub position: Vector2,
+    pub velocity: f32,
+    pub on_left_side: bool,
+}
+
+impl Paddle {
+    fn new(on_left_side: bool, config: &PongGameConfig) -> Paddle {
+        let pos = if on_left_side {
+            Vector2::new(0.0, 0.0)
+        } else {
+            Vector2::new(config.arena_size.x - config.paddle_size.x, 0.0)
+        };
+
+        Paddle {
+            position: pos,
+            velocity: 0.0,
+            on_left_side,
+        }
+    }
+    fn process_movement(&mut self, vertical_input: f32, config: &PongGameConfig) {
+        let dt = config.dt;
+        self.velocity += vertical_input * (config.paddle_force + config.paddle_friction) * dt;
+        let friction_effect = -self.velocity.signum() * config.paddle_friction * dt;
+        if self.velocity.abs() < friction_effect.abs() {
+            self.velocity = 0.0;
+        } else {
+            self.velocity += friction_effect;
+        }
+        self.position.y += self.velocity * dt;
+        if self.position.y <= 0.0 || self.position.y + config.paddle_size.y >= config.arena_size.y {
+            self.velocity *= -1.0;
+        }
+    }
+    fn get_ball_hit_x(&self, config: &PongGameConfig) -> f32 {
+        if self.on_left_side {
+            self.position.x + config.paddle_size.x + config.ball_size
+        } else {
+            self.position.x - config.ball_size
+        }
+    }
+
+    /// The angle a ball leaves the front of the paddle at, from straight across, when it hits
+    /// `hit_y` down from the paddle's top. Positive is down the court
+    fn bounce_angle(&self, hit_y: f32, config: &PongGameConfig) -> f32 {
+        // from -1 at the very top, where the ball only just touches, to 1 at the very bottom
+        let half_reach = config.paddle_size.y / 2.0 + config.ball_size;
+        let offset = ((hit_y - config.paddle_size.y / 2.0) / half_reach).clamp(-1.0, 1.0);
+        let max = config.max_bounce_angle;
+        (offset * max + self.velocity * config.paddle_spin).clamp(-max, max)
+    }
+
+    /// Sweeps the ball's path from `from` to `to` against the paddle as it moved from
+    /// `previous_position` to where it is now, so neither of them can skip through the other
+    /// in one frame however fast they go. Works relative to the paddle, where the ball is a
+    /// point and the paddle is grown by the ball's size on every side
+    fn sweep(
+        &self,
+        previous_position: Vector2,
+        from: Vector2,
+        to: Vector2,
+        config: &PongGameConfig,
+    ) -> Option<Contact> {
+        let start = from - previous_position;
+        let delta = (to - self.position) - start;
+        let min = Vector2::new(-config.ball_size, -config.ball_size);
+        let max = config.paddle_size + Vector2::new(1.0, 1.0) * config.ball_size;
+
+        if start.x >= min.x && start.x <= max.x && start.y >= min.y && start.y <= max.y {
+            // already touching, the paddle moved onto the ball or it was left on an edge. It's
+            // pushed out the nearest side, as long as it's heading into that side
+            let sides = [
+                (
+                    start.x - min.x,
+                    Vector2::new(-1.0, 0.0),
+                    Vector2::new(min.x, start.y),
+                ),
+                (
+                    max.x - start.x,
+                    Vector2::new(1.0, 0.0),
+                    Vector2::new(max.x, start.y),
+                ),
+                (
+                    start.y - min.y,
+                    Vector2::new(0.0, -1.0),
+                    Vector2::new(start.x, min.y),
+                ),
+                (
+                    max.y - start.y,
+                    Vector2::new(0.0, 1.0),
+                    Vector2::new(start.x, max.y),
+                ),
+            ];
+            let (_, normal, point) = sides
+                .iter()
+                .copied()
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .unwrap();
+            if delta.dot(normal) >= 0.0 {
+                return None;
+            }
+            return Some(Contact {
+                time: 0.0,
+                normal,
+                point,
+            });
+        }
+
+        // the latest of the times the path crosses into the box along each axis is when it's
+        // inside on both, unless it has already left along the other one by then
+        let axes = [
+            (start.x, delta.x, min.x, max.x, Vector2::new(1.0, 0.0)),
+            (start.y, delta.y, min.y, max.y, Vector2::new(0.0, 1.0)),
+        ];
+        let mut enter = 0.0f32;
+        let mut exit = 1.0f32;
+        let mut normal = Vector2::default();
+        for (start, delta, min, max, axis) in axes.iter() {
+            if *delta == 0.0 {
+                if start < min || start > max {
+                    return None;
+                }
+                continue;
+            }
+            let to_min = (min - start) / delta;
+            let to_max = (max - start) / delta;
+            let (near, far, side) = if to_min < to_max {
+                (to_min, to_max, *axis * -1.0)
+            } else {
+                (to_max, to_min, *axis)
+            };
+            if near > enter {
+                enter = near;
+                normal = side;
+            }
+            exit = exit.min(far);
+            if enter > exit {
+                return None;
+            }
+        }
+        Some(Contact {
+            time: enter,
+            normal,
+            point: start + delta * enter,
+        })
+    }
+}
+
+/// Where a ball's path first touches a paddle
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct Contact {
+    // how far along the path, from 0 to 1
+    time: f32,
+    // which way the side it touched faces, (±1, 0) for the front and back, (0, ±1) for the
+    // top and bottom edges
+    normal: Vector2,
+    // where the ball is when it touches, relative to where the paddle is now
+    point: Vector2,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Ball {
+    pub position: Vector2,
+    pub movement: Vector2,
+    pub increased_speed: f32,
+}
+
+impl Ball {
+    fn new(config: &PongGameConfig) -> Ball {
+        Ball {
+            position: config.arena_size / 2.0,
+            movement: Vector2::new(0.0, 0.0),
+            increased_speed: 0.0,
+        }
+    }
+
+    /// Sits still in front of the middle of the serving paddle, a ball's width clear of it so
+    /// it doesn't count as a hit
+    fn hold(&mut self, paddle: &Paddle, config: &PongGameConfig) {
+        let gap = if paddle.on_left_side {
+            config.ball_size
+        } else {
+            -config.ball_size
+        };
+        let y = paddle.position.y + config.paddle_size.y / 2.0;
+        self.position = Vector2::new(
+            paddle.get_ball_hit_x(config) + gap,
+            y.clamp(config.ball_size, config.arena_size.y - config.ball_size),
+        );
+        self.movement = Vector2::new(0.0, 0.0);
+        self.increased_speed = 0.0;
+    }
+
+    /// Sends the ball away from the serving paddle at a random angle, turned further the way
+    /// the paddle is moving like a hit
+    fn launch(&mut self, paddle: &Paddle, rng: &mut Rng, config: &PongGameConfig) {
+        let spread = config.serve_spread;
+        self.movement = Vector2::new(
+            if paddle.on_left_side { 1.0 } else { -1.0 },
+            rng.range_f32(-spread, spread),
+        );
+        if paddle.velocity.abs() > 0.01 {
+            self.movement.y += paddle.velocity.signum();
+        }
+        self.movement.normalize();
+    }
+
+    fn speed(&self, config: &PongGameConfig) -> f32 {
+        config.ball_speed + self.increased_speed
+    }
+
+    /// Turns the ball around off whichever side of `paddle` it touched
+    fn bounce_off(&mut self, paddle: &Paddle, contact: &Contact, config: &PongGameConfig) {
+        if contact.normal.x != 0.0 {
+            // off the front, aimed by where on the paddle it hit
+            let angle = paddle.bounce_angle(contact.point.y, config);
+            self.movement = Vector2::new(contact.normal.x * angle.cos(), angle.sin());
+            return;
+        }
+
+        // off the top or bottom edge, it glances away and carries on across
+        self.movement.y = contact.normal.y * self.movement.y.abs();
+        if paddle.velocity.abs() > 0.01 {
+            self.movement.y += paddle.velocity.signum();
+        }
+        self.movement.normalize();
+    }
+
+    /// Moves along the movement vector and bounces on paddles and walls. The paddles have
+    /// already moved this frame, from `previous_paddle_positions`
+    fn process_movement(
+        &mut self,
+        config: &PongGameConfig,
+        paddles: [&Paddle; 2],
+        previous_paddle_positions: [Vector2; 2],
+        events: &mut Vec<SimEvent>,
+    ) {
+        let dt = config.dt;
+        // bounce off of whichever paddle the ball reaches first, then go the rest of the way
+        let to = self.position + self.movement * dt * self.speed(config);
+        let mut first: Option<(usize, Contact)> = None;
+        for (side, paddle) in paddles.iter().enumerate() {
+            if let Some(contact) =
+                paddle.sweep(previous_paddle_positions[side], self.position, to, config)
+            {
+                if first.is_none_or(|(_, first)| contact.time < first.time) {
+                    first = Some((side, contact));
+                }
+            }
+        }
+        match first {
+            Some((side, contact)) => {
+                let paddle = paddles[side];
+                self.position = paddle.position + contact.point;
+                self.bounce_off(paddle, &contact, config);
+                events.push(SimEvent::PaddleHit {
+                    side,
+                    speed: self.speed(config),
+                });
+                self.position += self.movement * dt * self.speed(config) * (1.0 - contact.time);
+            }
+            None => self.position = to,
+        }
+
+        // bounce off of top and bottom walls. A ball resting against a wall isn't a bounce
+        if self.position.y <= config.ball_size {
+            if self.movement.y < 0.0 {
+                events.push(SimEvent::WallBounce);
+            }
+            self.movement.y *= -1.0;
+            self.position.y = config.ball_size;
+        }
+        if self.position.y >= config.arena_size.y - config.ball_size {
+            if self.movement.y > 0.0 {
+                events.push(SimEvent::WallBounce);
+            }
+            self.movement.y *= -1.0;
+            self.position.y = config.arena_size.y - config.ball_size;
+        }
+
+        // increase speed over time
+        self.increased_speed += dt * 50.0;
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Score {
+    pub value: i32,
+    pub left_side: bool,
+}
+
+impl Score {
+    fn new(on_left_side: bool) -> Score {
+        Score {
+            value: 0,
+            left_side: on_left_side,
+        }
+    }
+}
+
+/// Pulls the fields of a serialized PongGameState back out in the order they were written
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl SnapshotReader<'_> {
+    fn read_4(&mut self) -> [u8; 4] {
+        let mut out = [0u8; 4];
+        out.copy_from_slice(&self.bytes[self.cursor..self.cursor + 4]);
+        self.cursor += 4;
+        out
+    }
+    fn f32(&mut self) -> f32 {
+        f32::from_le_bytes(self.read_4())
+    }
+    fn i32(&mut self) -> i32 {
+        i32::from_le_bytes(self.read_4())
+    }
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.read_4())
+    }
+    fn u64(&mut self) -> u64 {
+        let mut out = [0u8; 8];
+        out.copy_from_slice(&self.bytes[self.cursor..self.cursor + 8]);
+        self.cursor += 8;
+        u64::from_le_bytes(out)
+    }
+    fn u8(&mut self) -> u8 {
+        self.cursor += 1;
+        self.bytes[self.cursor - 1]
+    }
+    fn bool(&mut self) -> bool {
+        self.u8() != 0
+    }
+    fn vector2(&mut self) -> Vector2 {
+        Vector2::new(self.f32(), self.f32())
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct PongGameState {
+    pub left_paddle: Paddle,
+    pub right_paddle: Paddle,
+    pub ball: Ball,
+    pub left_score: Score,
+    pub right_score: Score,
+    // frames into the current game, for the time limit
+    pub game_frames: u32,
+    pub games_won: [u32; 2],
+    pub phase: MatchPhase,
+    pub server: usize,
+    pub serve_phase: ServePhase,
+    // anything random in the simulation comes from here, seeded the same for both players
+    pub rng: Rng,
+    // not serialized, the same the whole match
+    pub config: PongGameConfig,
+}
+
+impl Default for PongGameState {
+    fn default() -> Self {
+        PongGameState::new()
+    }
+}
+
+impl PongGameState {
+    /// For tests and tools, a real match is seeded by the relay
+    pub fn new() -> PongGameState {
+        PongGameState::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u64) -> PongGameState {
+        PongGameState::with_config(GAME_CONFIG, seed)
+    }
+
+    pub fn with_rules(rules: MatchRules, seed: u64) -> PongGameState {
+        PongGameState::with_config(
+            PongGameConfig {
+                rules,
+                ..GAME_CONFIG
+            },
+            seed,
+        )
+    }
+
+    pub fn with_config(config: PongGameConfig, seed: u64) -> PongGameState {
+        let mut state = PongGameState {
+            left_paddle: Paddle::new(true, &config),
+            right_paddle: Paddle::new(false, &config),
+            ball: Ball::new(&config),
+            left_score: Score::new(true),
+            right_score: Score::new(false),
+            game_frames: 0,
+            games_won: [0, 0],
+            phase: MatchPhase::Playing,
+            server: 0,
+            serve_phase: ServePhase::InPlay,
+            rng: Rng::new(seed),
+            config,
+        };
+        state.serve(0, &mut Vec::new());
+        state
+    }
+
+    pub fn winner(&self) -> Option<usize> {
+        match self.phase {
+            MatchPhase::MatchOver { winner } => Some(winner),
+            _ => None,
+        }
+    }
+
+    fn scores(&self) -> [i32; 2] {
+        [self.left_score.value, self.right_score.value]
+    }
+
+    /// Whoever has won the current game under the rules, checked after every point
+    fn game_winner(&self) -> Option<usize> {
+        let scores = self.scores();
+        (0..2).find(|&side| {
+            let lead = scores[side] - scores[1 - side];
+            if self.phase == MatchPhase::Overtime {
+                return lead > 0;
+            }
+            let lead_needed = if self.config.rules.win_by_two { 2 } else { 1 };
+            scores[side] >= self.config.rules.points_to_win && lead >= lead_needed
+        })
+    }
+
+    fn serving_paddle(&self) -> &Paddle {
+        if self.server == 0 {
+            &self.left_paddle
+        } else {
+            &self.right_paddle
+        }
+    }
+
+    /// Who serves next, after `point_winner` won the last point or game
+    fn next_server(&self, point_winner: usize) -> usize {
+        match self.config.rules.serve_order {
+            ServeOrder::Loser => 1 - point_winner,
+            ServeOrder::Alternate => 1 - self.server,
+        }
+    }
+
+    fn serve(&mut self, server: usize, events: &mut Vec<SimEvent>) {
+        self.server = server;
+        self.serve_phase = ServePhase::Countdown {
+            frames_left: self.config.serve_countdown_frames,
+        };
+        let paddle = self.serving_paddle().clone();
+        self.ball.hold(&paddle, &self.config);
+        events.push(SimEvent::Serve { server });
+    }
+
+    /// Counts the serve down and keeps the ball on the server's paddle until it's launched
+    fn process_serve(&mut self, inputs: &[PongInputState; 2], events: &mut Vec<SimEvent>) {
+        let paddle = self.serving_paddle().clone();
+        self.serve_phase = match self.serve_phase {
+            ServePhase::Countdown { frames_left } if frames_left > 1 => {
+                let seconds_left = self.config.seconds_left(frames_left - 1);
+                if seconds_left < self.config.seconds_left(frames_left) {
+                    events.push(SimEvent::ServeCountdown { seconds_left });
+                }
+                ServePhase::Countdown {
+                    frames_left: frames_left - 1,
+                }
+            }
+            ServePhase::Countdown { .. } => {
+                events.push(SimEvent::ServeCountdown { seconds_left: 0 });
+                ServePhase::Holding {
+                    frames_left: self.config.serve_hold_frames,
+                }
+            }
+            ServePhase::Holding { frames_left }
+                if frames_left > 1
+                    && !inputs[self.server].pressed(PongInputState::BUTTON_SERVE) =>
+            {
+                ServePhase::Holding {
+                    frames_left: frames_left - 1,
+                }
+            }
+            ServePhase::Holding { .. } | ServePhase::InPlay => {
+                self.ball.launch(&paddle, &mut self.rng, &self.config);
+                events.push(SimEvent::Launch {
+                    server: self.server,
+                });
+                ServePhase::InPlay
+            }
+        };
+        if self.serve_phase != ServePhase::InPlay {
+            self.ball.hold(&paddle, &self.config);
+        }
+    }
+
+    fn end_game(&mut self, winner: usize, events: &mut Vec<SimEvent>) {
+        self.games_won[winner] += 1;
+        events.push(SimEvent::GameOver { winner });
+        if self.games_won[winner] >= self.config.rules.games_to_win() {
+            self.phase = MatchPhase::MatchOver { winner };
+            events.push(SimEvent::MatchOver { winner });
+            return;
+        }
+        self.left_score.value = 0;
+        self.right_score.value = 0;
+        self.game_frames = 0;
+        self.phase = MatchPhase::Playing;
+        self.serve(self.next_server(winner), events);
+    }
+
+    fn process_paddle_input(&mut self, i: f32, is_on_left_side: bool) {
+        (if is_on_left_side {
+            &mut self.left_paddle
+        } else {
+            &mut self.right_paddle
+        })
+        .process_movement(i, &self.config);
+    }
+
+    /// Simulates one frame and returns what happened during it, in the order it happened
+    pub fn process_logic(&mut self, inputs: &[PongInputState; 2]) -> Vec<SimEvent> {
+        let mut events = Vec::new();
+        if self.winner().is_some() {
+            return events;
+        }
+        self.game_frames += 1;
+        let previous_paddle_positions = [self.left_paddle.position, self.right_paddle.position];
+        self.process_paddle_input(inputs[0].input, true);
+        self.process_paddle_input(inputs[1].input, false);
+
+        if self.serve_phase != ServePhase::InPlay {
+            self.process_serve(inputs, &mut events);
+        } else {
+            self.ball.process_movement(
+                &self.config,
+                [&self.left_paddle, &self.right_paddle],
+                previous_paddle_positions,
+                &mut events,
+            );
+        }
+        let scorer = if self.ball.position.x <= -self.config.ball_size {
+            self.right_score.value += 1;
+            Some(1)
+        } else if self.ball.position.x >= self.config.arena_size.x + self.config.ball_size {
+            self.left_score.value += 1;
+            Some(0)
+        } else {
+            None
+        };
+        if let Some(scorer) = scorer {
+            events.push(SimEvent::Goal { scorer });
+            match self.game_winner() {
+                Some(winner) => self.end_game(winner, &mut events),
+                None => self.serve(self.next_server(scorer), &mut events),
+            }
+        }
+
+        if let Some(time_limit) = self.config.rules.time_limit_frames {
+            if self.phase == MatchPhase::Playing && self.game_frames >= time_limit {
+                let scores = self.scores();
+                match scores[0].cmp(&scores[1]) {
+                    Ordering::Greater => self.end_game(0, &mut events),
+                    Ordering::Less => self.end_game(1, &mut events),
+                    Ordering::Equal => {
+                        self.phase = MatchPhase::Overtime;
+                        events.push(SimEvent::Overtime);
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    /// A cheap fingerprint of the whole state, FNV-1a over `to_bytes`. Two states with the same
+    /// hash are as good as equal
+    pub fn state_hash(&self) -> u64 {
+        fnv1a(&self.to_bytes())
+    }
+
+    /// Every field in declaration order, little endian, for spectators to start from
+    pub fn to_bytes(&self) -> [u8; SNAPSHOT_STATE_SIZE] {
+        let mut out = Vec::with_capacity(SNAPSHOT_STATE_SIZE);
+        for paddle in [&self.left_paddle, &self.right_paddle].iter() {
+            out.extend_from_slice(&paddle.position.x.to_le_bytes());
+            out.extend_from_slice(&paddle.position.y.to_le_bytes());
+            out.extend_from_slice(&paddle.velocity.to_le_bytes());
+            out.push(paddle.on_left_side as u8);
+        }
+        out.extend_from_slice(&self.ball.position.x.to_le_bytes());
+        out.extend_from_slice(&self.ball.position.y.to_le_bytes());
+        out.extend_from_slice(&self.ball.movement.x.to_le_bytes());
+        out.extend_from_slice(&self.ball.movement.y.to_le_bytes());
+        out.extend_from_slice(&self.ball.increased_speed.to_le_bytes());
+        for score in [&self.left_score, &self.right_score].iter() {
+            out.extend_from_slice(&score.value.to_le_bytes());
+            out.push(score.left_side as u8);
+        }
+        out.extend_from_slice(&self.game_frames.to_le_bytes());
+        for games_won in self.games_won.iter() {
+            out.extend_from_slice(&games_won.to_le_bytes());
+        }
+        let (phase, winner) = match self.phase {
+            MatchPhase::Playing => (0, 0),
+            MatchPhase::Overtime => (1, 0),
+            MatchPhase::MatchOver { winner } => (2, winner as u8),
+        };
+        out.push(phase);
+        out.push(winner);
+        let (serve_phase, frames_left) = match self.serve_phase {
+            ServePhase::Countdown { frames_left } => (0, frames_left),
+            ServePhase::Holding { frames_left } => (1, frames_left),
+            ServePhase::InPlay => (2, 0),
+        };
+        out.push(self.server as u8);
+        out.push(serve_phase);
+        out.extend_from_slice(&frames_left.to_le_bytes());
+        out.extend_from_slice(&self.rng.state.to_le_bytes());
+
+        let mut bytes = [0u8; SNAPSHOT_STATE_SIZE];
+        bytes.copy_from_slice(&out);
+        bytes
+    }
+
+    /// The state `to_bytes` wrote, for a match played with `config` and its rules
+    pub fn from_bytes(bytes: &[u8; SNAPSHOT_STATE_SIZE], config: PongGameConfig) -> PongGameState {
+        let mut reader = SnapshotReader { bytes, cursor: 0 };
+        let read_paddle = |reader: &mut SnapshotReader| Paddle {
+            position: reader.vector2(),
+            velocity: reader.f32(),
+            on_left_side: reader.bool(),
+        };
+        let left_paddle = read_paddle(&mut reader);
+        let right_paddle = read_paddle(&mut reader);
+        let ball = Ball {
+            position: reader.vector2(),
+            movement: reader.vector2(),
+            increased_speed: reader.f32(),
+        };
+        let left_score = Score {
+            value: reader.i32(),
+            left_side: reader.bool(),
+        };
+        let right_score = Score {
+            value: reader.i32(),
+            left_side: reader.bool(),
+        };
+        let game_frames = reader.u32();
+        let games_won = [reader.u32(), reader.u32()];
+        let phase = match (reader.u8(), reader.u8()) {
+            (1, _) => MatchPhase::Overtime,
+            (2, winner) => MatchPhase::MatchOver {
+                winner: winner as usize,
+            },
+            _ => MatchPhase::Playing,
+        };
+        let server = reader.u8() as usize;
+        let serve_phase = match (reader.u8(), reader.u32()) {
+            (0, frames_left) => ServePhase::Countdown { frames_left },
+            (1, frames_left) => ServePhase::Holding { frames_left },
+            _ => ServePhase::InPlay,
+        };
+        let rng = Rng::new(reader.u64());
+        PongGameState {
+            left_paddle,
+            right_paddle,
+            ball,
+            left_score,
+            right_score,
+            game_frames,
+            games_won,
+            phase,
+            server,
+            serve_phase,
+            rng,
+            config,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic() {
+        let mut states: [PongGameState; 7] = [
+            PongGameState::new(),
+            PongGameState::new(),
+            PongGameState::new(),
+            PongGameState::new(),
+            PongGameState::new(),
+            PongGameState::new(),
+            PongGameState::new(),
+        ];
+
+        for s in states.iter_mut() {
+            for frame in 0..5000 {
+                let elapsed_time = (frame as f32) * GAME_CONFIG.dt;
+                s.process_logic(&[
+                    PongInputState::from_input(elapsed_time.sin()),
+                    PongInputState::from_input(elapsed_time.cos()),
+                ]);
+            }
+        }
+
+        for s in states.iter().skip(1) {
+            assert_eq!(&states[0], s);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let mut state = PongGameState::new();
+        for frame in 0..1000 {
+            let elapsed_time = (frame as f32) * GAME_CONFIG.dt;
+            state.process_logic(&[
+                PongInputState::from_input(elapsed_time.cos()),
+                PongInputState::from_input(-1.0),
+            ]);
+        }
+
+        let mut restored = PongGameState::from_bytes(&state.to_bytes(), GAME_CONFIG);
+        assert_eq!(restored, state);
+
+        // and it keeps simulating the same way
+        for _ in 0..1000 {
+            let inputs = [
+                PongInputState::from_input(1.0),
+                PongInputState::from_input(0.5),
+            ];
+            state.process_logic(&inputs);
+            restored.process_logic(&inputs);
+        }
+        assert_eq!(restored, state);
+        assert_eq!(restored.state_hash(), state.state_hash());
+        assert_ne!(state.state_hash(), PongGameState::new().state_hash());
+    }
+
+    #[test]
+    fn test_events() {
+        // a game long enough that nobody wins it
+        let mut state = PongGameState::with_rules(
+            MatchRules {
+                points_to_win: i32::MAX,
+                ..MATCH_RULES
+            },
+            0,
+        );
+        let mut events = Vec::new();
+        for frame in 0..5000 {
+            let elapsed_time = (frame as f32) * GAME_CONFIG.dt;
+            events.extend(state.process_logic(&[
+                PongInputState::from_input(elapsed_time.sin()),
+                PongInputState::from_input(elapsed_time.cos()),
+            ]));
+        }
+
+        let goals = |scorer| {
+            events
+                .iter()
+                .filter(|e| **e == SimEvent::Goal { scorer })
+                .count() as i32
+        };
+        assert_eq!(goals(0), state.left_score.value);
+        assert_eq!(goals(1), state.right_score.value);
+        // every goal is followed by a serve, and every serve was launched in the end
+        for pair in events.windows(2) {
+            if let SimEvent::Goal { scorer } = pair[0] {
+                assert_eq!(pair[1], SimEvent::Serve { server: 1 - scorer });
+            }
+        }
+        let launches = events
+            .iter()
+            .filter(|e| matches!(e, SimEvent::Launch { .. }))
+            .count() as i32;
+        assert!(launches >= goals(0) + goals(1));
+        assert!(events.contains(&SimEvent::WallBounce));
+        for side in 0..2 {
+            assert!(events.iter().any(|e| match e {
+                SimEvent::PaddleHit { side: s, speed } => *s == side && *speed > 400.0,
+                _ => false,
+            }));
+        }
+    }
+
+    // puts the ball in play past the goal `scorer` is shooting at and plays a frame
+    fn score(state: &mut PongGameState, scorer: usize) -> Vec<SimEvent> {
+        state.serve_phase = ServePhase::InPlay;
+        state.ball.position.x = if scorer == 0 {
+            GAME_CONFIG.arena_size.x + GAME_CONFIG.ball_size * 2.0
+        } else {
+            -GAME_CONFIG.ball_size * 2.0
+        };
+        state.process_logic(&[
+            PongInputState::from_input(0.0),
+            PongInputState::from_input(0.0),
+        ])
+    }
+
+    #[test]
+    fn test_win_by_two() {
+        let mut state = PongGameState::with_rules(
+            MatchRules {
+                points_to_win: 3,
+                ..MATCH_RULES
+            },
+            0,
+        );
+        for _ in 0..2 {
+            score(&mut state, 0);
+            score(&mut state, 1);
+        }
+        // 3-2 isn't enough
+        assert_eq!(
+            score(&mut state, 0),
+            vec![SimEvent::Goal { scorer: 0 }, SimEvent::Serve { server: 1 }]
+        );
+        score(&mut state, 1);
+        score(&mut state, 1);
+        assert_eq!(
+            score(&mut state, 1),
+            vec![
+                SimEvent::Goal { scorer: 1 },
+                SimEvent::GameOver { winner: 1 },
+                SimEvent::MatchOver { winner: 1 },
+            ]
+        );
+        assert_eq!(state.winner(), Some(1));
+        assert_eq!((state.left_score.value, state.right_score.value), (3, 5));
+
+        // without it the first to 3 wins
+        let mut state = PongGameState::with_rules(
+            MatchRules {
+                points_to_win: 3,
+                win_by_two: false,
+                ..MATCH_RULES
+            },
+            0,
+        );
+        for _ in 0..2 {
+            score(&mut state, 0);
+            score(&mut state, 1);
+        }
+        score(&mut state, 0);
+        assert_eq!(state.winner(), Some(0));
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let rules = MatchRules {
+            time_limit_frames: Some(30),
+            ..MATCH_RULES
+        };
+        let wait = |state: &mut PongGameState, frames| {
+            let mut events = Vec::new();
+            for _ in 0..frames {
+                events.extend(state.process_logic(&[
+                    PongInputState::from_input(0.0),
+                    PongInputState::from_input(0.0),
+                ]));
+            }
+            events
+        };
+
+        // short enough that the ball doesn't reach either end in the meantime.
+        // Whoever is ahead when time runs out wins
+        let mut state = PongGameState::with_rules(rules, 0);
+        score(&mut state, 1);
+        assert!(wait(&mut state, 29).contains(&SimEvent::MatchOver { winner: 1 }));
+
+        // a tie goes to sudden death
+        let mut state = PongGameState::with_rules(rules, 0);
+        score(&mut state, 1);
+        score(&mut state, 0);
+        assert!(wait(&mut state, 29).contains(&SimEvent::Overtime));
+        assert_eq!(state.phase, MatchPhase::Overtime);
+        assert!(!wait(&mut state, 30).contains(&SimEvent::Overtime));
+        score(&mut state, 0);
+        assert_eq!(state.winner(), Some(0));
+    }
+
+    #[test]
+    fn test_best_of() {
+        let mut state = PongGameState::with_rules(
+            MatchRules {
+                points_to_win: 2,
+                best_of: 3,
+                ..MATCH_RULES
+            },
+            0,
+        );
+        score(&mut state, 0);
+        assert_eq!(
+            score(&mut state, 0),
+            vec![
+                SimEvent::Goal { scorer: 0 },
+                SimEvent::GameOver { winner: 0 },
+                SimEvent::Serve { server: 1 },
+            ]
+        );
+        // the next game starts from nothing
+        assert_eq!((state.left_score.value, state.right_score.value), (0, 0));
+        assert_eq!(state.game_frames, 0);
+        assert_eq!(state.games_won, [1, 0]);
+
+        for _ in 0..2 {
+            score(&mut state, 1);
+        }
+        assert_eq!(state.winner(), None);
+        for _ in 0..2 {
+            score(&mut state, 1);
+        }
+        assert_eq!(state.winner(), Some(1));
+        assert_eq!(state.games_won, [1, 2]);
+
+        // once it's over nothing moves, and that survives a snapshot
+        let finished = state.clone();
+        assert_eq!(score(&mut state, 0), vec![]);
+        state.ball.position = finished.ball.position;
+        assert_eq!(state, finished);
+        let restored = PongGameState::from_bytes(&state.to_bytes(), GAME_CONFIG);
+        assert_eq!(restored.phase, MatchPhase::MatchOver { winner: 1 });
+        assert_eq!(restored.games_won, [1, 2]);
+    }
+
+    #[test]
+    fn test_serve() {
+        let idle = [
+            PongInputState::from_input(0.0),
+            PongInputState::from_input(0.0),
+        ];
+        let mut launch = idle;
+        launch[0].buttons = PongInputState::BUTTON_SERVE;
+
+        // the host serves first, and can't launch during the countdown. Every second of it
+        // is marked so it can be heard
+        let mut state = PongGameState::new();
+        assert_eq!(state.server, 0);
+        let countdown: Vec<SimEvent> = (0..GAME_CONFIG.serve_countdown_frames)
+            .flat_map(|_| state.process_logic(&launch))
+            .collect();
+        assert_eq!(
+            countdown,
+            vec![
+                SimEvent::ServeCountdown { seconds_left: 1 },
+                SimEvent::ServeCountdown { seconds_left: 0 }
+            ]
+        );
+        assert_eq!(
+            state.serve_phase,
+            ServePhase::Holding {
+                frames_left: GAME_CONFIG.serve_hold_frames
+            }
+        );
+
+        // the ball goes where the paddle goes
+        let mut moving = idle;
+        moving[0].input = 1.0;
+        for _ in 0..10 {
+            state.process_logic(&moving);
+        }
+        let held_at = state.ball.position;
+        assert!(held_at.y > GAME_CONFIG.paddle_size.y / 2.0);
+        assert!(held_at.x > GAME_CONFIG.paddle_size.x);
+
+        // and survives a snapshot
+        assert_eq!(
+            PongGameState::from_bytes(&state.to_bytes(), GAME_CONFIG),
+            state
+        );
+
+        assert_eq!(
+            state.process_logic(&launch),
+            vec![SimEvent::Launch { server: 0 }]
+        );
+        assert_eq!(state.serve_phase, ServePhase::InPlay);
+        state.process_logic(&idle);
+        assert!(state.ball.position.x > held_at.x);
+
+        // held on to for too long, it goes by itself
+        let mut state = PongGameState::new();
+        let mut events = Vec::new();
+        for _ in 0..GAME_CONFIG.serve_countdown_frames + GAME_CONFIG.serve_hold_frames {
+            events.extend(state.process_logic(&idle));
+        }
+        assert_eq!(events.last(), Some(&SimEvent::Launch { server: 0 }));
+        assert_eq!(events.len(), 3);
+    }
+
+    #[test]
+    fn test_seeded_serves() {
+        let launch = [
+            PongInputState {
+                buttons: PongInputState::BUTTON_SERVE,
+                ..PongInputState::from_input(0.0)
+            },
+            PongInputState::from_input(0.0),
+        ];
+        let serve_with_seed = |seed| {
+            let mut state = PongGameState::with_seed(seed);
+            for _ in 0..=GAME_CONFIG.serve_countdown_frames {
+                state.process_logic(&launch);
+            }
+            assert_eq!(state.serve_phase, ServePhase::InPlay);
+            state.ball.movement
+        };
+        // the same seed serves the same way every time, different seeds don't
+        assert_eq!(serve_with_seed(7), serve_with_seed(7));
+        assert_ne!(serve_with_seed(7), serve_with_seed(8));
+        let slope = serve_with_seed(7).y / serve_with_seed(7).x;
+        assert!(slope.abs() < GAME_CONFIG.serve_spread);
+    }
+
+    #[test]
+    fn test_serve_order() {
+        let mut state = PongGameState::new();
+        score(&mut state, 1);
+        assert_eq!(state.server, 0);
+        score(&mut state, 0);
+        assert_eq!(state.server, 1);
+
+        let mut state = PongGameState::with_rules(
+            MatchRules {
+                serve_order: ServeOrder::Alternate,
+                ..MATCH_RULES
+            },
+            0,
+        );
+        let servers: Vec<usize> = (0..3)
+            .map(|_| {
+                score(&mut state, 0);
+                state.server
+            })
+            .collect();
+        assert_eq!(servers, vec![1, 0, 1]);
+    }
+
+    // a left paddle part way down the court and a ball heading at it `speed` units per step
+    fn fast_ball(speed: f32, start: Vector2, towards: Vector2) -> (Ball, [Paddle; 2]) {
+        let mut left = Paddle::new(true, &GAME_CONFIG);
+        left.position.y = 300.0;
+        let ball = Ball {
+            position: start,
+            movement: (towards - start).normalized(),
+            increased_speed: speed * 60.0 - GAME_CONFIG.ball_speed,
+        };
+        (ball, [left, Paddle::new(false, &GAME_CONFIG)])
+    }
+
+    #[test]
+    fn test_no_tunneling() {
+        let mut rng = Rng::new(7);
+        for speed in [100.0, 1000.0, 5000.0, 100000.0] {
+            for _ in 0..100 {
+                // aimed at the paddle's front face, clear of the corners where it would glance
+                // off an edge instead
+                let target = Vector2::new(0.0, 300.0 + rng.range_f32(20.0, 155.0));
+                let start = Vector2::new(500.0, rng.range_f32(0.0, 800.0));
+                let (mut ball, paddles) = fast_ball(speed, start, target);
+                let positions = [paddles[0].position, paddles[1].position];
+
+                let mut events = Vec::new();
+                for _ in 0..(1000.0 / speed).ceil() as usize {
+                    ball.process_movement(
+                        &GAME_CONFIG,
+                        [&paddles[0], &paddles[1]],
+                        positions,
+                        &mut events,
+                    );
+                    if !events.is_empty() {
+                        break;
+                    }
+                }
+                assert!(
+                    matches!(events.first(), Some(SimEvent::PaddleHit { side: 0, .. })),
+                    "missed the paddle at {} from {:?}",
+                    speed,
+                    start
+                );
+                assert!(ball.movement.x > 0.0);
+                assert!(ball.position.x >= GAME_CONFIG.paddle_size.x + GAME_CONFIG.ball_size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_edge_hits() {
+        let paddle_top = 300.0 - GAME_CONFIG.ball_size;
+        let paddle_bottom = 300.0 + GAME_CONFIG.paddle_size.y + GAME_CONFIG.ball_size;
+
+        // dropping onto the top edge glances back up rather than jumping out in front
+        let (mut ball, paddles) =
+            fast_ball(200.0, Vector2::new(10.0, 250.0), Vector2::new(12.0, 400.0));
+        let positions = [paddles[0].position, paddles[1].position];
+        let mut events = Vec::new();
+        ball.process_movement(
+            &GAME_CONFIG,
+            [&paddles[0], &paddles[1]],
+            positions,
+            &mut events,
+        );
+        assert_eq!(events.len(), 1);
+        assert!(ball.movement.y < 0.0);
+        assert!(ball.position.y <= paddle_top);
+
+        // and the same off the bottom
+        let (mut ball, paddles) =
+            fast_ball(200.0, Vector2::new(10.0, 550.0), Vector2::new(12.0, 400.0));
+        let mut events = Vec::new();
+        ball.process_movement(
+            &GAME_CONFIG,
+            [&paddles[0], &paddles[1]],
+            positions,
+            &mut events,
+        );
+        assert_eq!(events.len(), 1);
+        assert!(ball.movement.y > 0.0);
+        assert!(ball.position.y >= paddle_bottom);
+
+        // a paddle swept past a slow ball in one step pushes it out the top instead
+        let (mut ball, mut paddles) =
+            fast_ball(1.0, Vector2::new(10.0, 250.0), Vector2::new(11.0, 250.0));
+        paddles[0].position.y = 100.0;
+        let positions = [Vector2::new(0.0, 600.0), paddles[1].position];
+        let mut events = Vec::new();
+        ball.process_movement(
+            &GAME_CONFIG,
+            [&paddles[0], &paddles[1]],
+            positions,
+            &mut events,
+        );
+        assert_eq!(events.len(), 1);
+        assert!(ball.position.y <= paddles[0].position.y - GAME_CONFIG.ball_size);
+    }
+
+    #[test]
+    fn test_bounce_angles() {
+        let max = GAME_CONFIG.max_bounce_angle;
+        let middle = GAME_CONFIG.paddle_size.y / 2.0;
+        let half_reach = middle + GAME_CONFIG.ball_size;
+        let close = |a: f32, b: f32| (a - b).abs() < 0.0001;
+
+        // straight across off the middle, out to the maximum off the ends, and evenly between
+        let mut paddle = Paddle::new(true, &GAME_CONFIG);
+        assert!(close(paddle.bounce_angle(middle, &GAME_CONFIG), 0.0));
+        assert!(close(
+            paddle.bounce_angle(-GAME_CONFIG.ball_size, &GAME_CONFIG),
+            -max
+        ));
+        assert!(close(
+            paddle.bounce_angle(middle + half_reach, &GAME_CONFIG),
+            max
+        ));
+        assert!(close(
+            paddle.bounce_angle(middle + half_reach / 2.0, &GAME_CONFIG),
+            max / 2.0
+        ));
+        assert!(close(
+            paddle.bounce_angle(middle - half_reach / 4.0, &GAME_CONFIG),
+            -max / 4.0
+        ));
+        assert!(close(paddle.bounce_angle(1000.0, &GAME_CONFIG), max));
+
+        // a moving paddle adds its spin, but never past the maximum
+        paddle.velocity = 500.0;
+        assert!(close(
+            paddle.bounce_angle(middle, &GAME_CONFIG),
+            500.0 * GAME_CONFIG.paddle_spin
+        ));
+        assert!(close(
+            paddle.bounce_angle(middle + half_reach, &GAME_CONFIG),
+            max
+        ));
+        paddle.velocity = -500.0;
+        assert!(close(
+            paddle.bounce_angle(middle + half_reach, &GAME_CONFIG),
+            max - 500.0 * GAME_CONFIG.paddle_spin
+        ));
+        paddle.velocity = -100000.0;
+        assert!(close(
+            paddle.bounce_angle(middle + half_reach, &GAME_CONFIG),
+            -max
+        ));
+
+        // and the ball leaves along it, back across the court from either side
+        for (hit_y, right_side) in [(middle, false), (150.0, false), (20.0, true)] {
+            let y = 300.0 + hit_y;
+            let (mut ball, mut paddles) =
+                fast_ball(50.0, Vector2::new(500.0, y), Vector2::new(0.0, y));
+            if right_side {
+                ball.movement.x = 1.0;
+                paddles[1].position.y = 300.0;
+            }
+            let positions = [paddles[0].position, paddles[1].position];
+            let mut events = Vec::new();
+            while events.is_empty() {
+                ball.process_movement(
+                    &GAME_CONFIG,
+                    [&paddles[0], &paddles[1]],
+                    positions,
+                    &mut events,
+                );
+            }
+            let angle = paddles[right_side as usize].bounce_angle(hit_y, &GAME_CONFIG);
+            let across = if right_side { -1.0 } else { 1.0 };
+            assert!(close(ball.movement.x, across * angle.cos()));
+            assert!(close(ball.movement.y, angle.sin()));
+        }
+    }
+}
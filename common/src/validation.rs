@@ -0,0 +1,132 @@
+use std::fmt;
+
+use crate::PongInputState;
+
+/// Why an input couldn't have come from an honest client
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputViolation {
+    NotFinite,
+    OutOfRange,
+    // bits set that aren't any button
+    UnknownButtons { buttons: u32 },
+    FrameNotMonotonic { expected: u32, got: u32 },
+    // further ahead than an honest opponent can get before waiting for us
+    FrameTooFarAhead { newest_allowed: u32, got: u32 },
+}
+
+impl fmt::Display for InputViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputViolation::NotFinite => write!(f, "input is not a finite number"),
+            InputViolation::OutOfRange => write!(f, "input is outside of [-1, 1]"),
+            InputViolation::UnknownButtons { buttons } => {
+                write!(f, "buttons {:#x} aren't all real buttons", buttons)
+            }
+            InputViolation::FrameNotMonotonic { expected, got } => {
+                write!(
+                    f,
+                    "expected input for frame {}, got frame {}",
+                    expected, got
+                )
+            }
+            InputViolation::FrameTooFarAhead {
+                newest_allowed,
+                got,
+            } => write!(
+                f,
+                "input for frame {} is past the newest plausible frame {}",
+                got, newest_allowed
+            ),
+        }
+    }
+}
+
+/// Checks an input against what the keyboard can produce and the frame the sender should
+/// be on, which can't be past `newest_allowed`. Inputs for frames before
+/// `next_frame_expected` are resends, and are up to the caller to ignore before getting here
+pub fn validate_input(
+    input: &PongInputState,
+    next_frame_expected: u32,
+    newest_allowed: u32,
+) -> Result<(), InputViolation> {
+    if !input.input.is_finite() {
+        return Err(InputViolation::NotFinite);
+    }
+    if !(-1.0..=1.0).contains(&input.input) {
+        return Err(InputViolation::OutOfRange);
+    }
+    if input.buttons & !PongInputState::ALL_BUTTONS != 0 {
+        return Err(InputViolation::UnknownButtons {
+            buttons: input.buttons,
+        });
+    }
+    if input.frame > newest_allowed {
+        return Err(InputViolation::FrameTooFarAhead {
+            newest_allowed,
+            got: input.frame,
+        });
+    }
+    if input.frame != next_frame_expected {
+        return Err(InputViolation::FrameNotMonotonic {
+            expected: next_frame_expected,
+            got: input.frame,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_input() {
+        let check = |input: &PongInputState| validate_input(input, 5, 100);
+        assert_eq!(check(&PongInputState::new(5, 1.0)), Ok(()));
+        assert_eq!(check(&PongInputState::new(5, -0.5)), Ok(()));
+        assert_eq!(
+            check(&PongInputState::new(5, f32::NAN)),
+            Err(InputViolation::NotFinite)
+        );
+        assert_eq!(
+            check(&PongInputState::new(5, f32::INFINITY)),
+            Err(InputViolation::NotFinite)
+        );
+        assert_eq!(
+            check(&PongInputState::new(5, 1.5)),
+            Err(InputViolation::OutOfRange)
+        );
+        let serve = PongInputState {
+            buttons: PongInputState::BUTTON_SERVE,
+            ..PongInputState::new(5, 0.0)
+        };
+        assert_eq!(check(&serve), Ok(()));
+        assert_eq!(
+            check(&PongInputState {
+                buttons: 6,
+                ..serve
+            }),
+            Err(InputViolation::UnknownButtons { buttons: 6 })
+        );
+        assert_eq!(
+            check(&PongInputState::new(7, 0.0)),
+            Err(InputViolation::FrameNotMonotonic {
+                expected: 5,
+                got: 7
+            })
+        );
+
+        // the next frame, but further ahead than the sender could honestly have got
+        assert_eq!(
+            validate_input(&PongInputState::new(101, 0.0), 101, 100),
+            Err(InputViolation::FrameTooFarAhead {
+                newest_allowed: 100,
+                got: 101
+            })
+        );
+        assert_eq!(
+            validate_input(&PongInputState::new(100, 0.0), 100, 100),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,117 @@
+// Plays every replay in tests/replays through the simulation and checks it ends up exactly where
+// it did when the expected results were saved. Any change to the physics shows up here.
+//
+// After an intentional change, regenerate the expected results with
+//     UPDATE_GOLDEN_REPLAYS=1 cargo test -p common --test golden_replays
+// and commit them along with the change.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use common::replay::{self, Replay};
+
+const UPDATE_VAR: &str = "UPDATE_GOLDEN_REPLAYS";
+
+/// What's saved next to each replay
+#[derive(PartialEq, Debug)]
+struct Outcome {
+    frames: u32,
+    hash: u64,
+    left_score: i32,
+    right_score: i32,
+}
+
+impl Outcome {
+    fn of(replay: &Replay) -> Outcome {
+        let mut state = replay.initial_state();
+        for frame in 0..replay.frames() {
+            state.process_logic(&replay.inputs_for(frame));
+        }
+        Outcome {
+            frames: replay.frames(),
+            hash: state.state_hash(),
+            left_score: state.left_score.value,
+            right_score: state.right_score.value,
+        }
+    }
+
+    fn to_text(&self) -> String {
+        format!(
+            "frames {}\nhash {:016x}\nscore {} {}\n",
+            self.frames, self.hash, self.left_score, self.right_score
+        )
+    }
+
+    fn from_text(text: &str) -> Option<Outcome> {
+        let mut frames = None;
+        let mut hash = None;
+        let mut score = None;
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("frames"), Some(value), None) => frames = value.parse().ok(),
+                (Some("hash"), Some(value), None) => hash = u64::from_str_radix(value, 16).ok(),
+                (Some("score"), Some(left), Some(right)) => {
+                    score = left.parse().ok().zip(right.parse().ok())
+                }
+                _ => return None,
+            }
+        }
+        let (left_score, right_score) = score?;
+        Some(Outcome {
+            frames: frames?,
+            hash: hash?,
+            left_score,
+            right_score,
+        })
+    }
+}
+
+fn corpus() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/replays");
+    let mut replays: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some(replay::FILE_EXTENSION))
+        .collect();
+    replays.sort();
+    replays
+}
+
+#[test]
+fn test_golden_replays() {
+    let update = env::var_os(UPDATE_VAR).is_some();
+    let replays = corpus();
+    assert!(!replays.is_empty(), "no replays in tests/replays");
+
+    let mut failures = Vec::new();
+    for path in replays.iter() {
+        let replay = Replay::load(path).unwrap();
+        let outcome = Outcome::of(&replay);
+        let expected_path = path.with_extension("expected");
+
+        if update {
+            fs::write(&expected_path, outcome.to_text()).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&expected_path)
+            .ok()
+            .and_then(|text| Outcome::from_text(&text));
+        if expected.as_ref() != Some(&outcome) {
+            failures.push(format!(
+                "{}\n  expected {:?}\n  got      {:?}",
+                path.display(),
+                expected,
+                outcome
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "replays no longer play out the same way, if that's intended rerun with {}=1:\n{}",
+        UPDATE_VAR,
+        failures.join("\n")
+    );
+}
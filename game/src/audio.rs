@@ -0,0 +1,299 @@
+use raylib::prelude::*;
+
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::rc::Rc;
+
+use common::sim::SimEvent;
+
+use crate::sim_events::{EventStatus, EventSubscriber, FrameEvent};
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SoundEffect {
+    PaddleHit,
+    WallBounce,
+    Goal,
+    Countdown,
+}
+
+impl SoundEffect {
+    pub const ALL: [SoundEffect; 4] = [
+        SoundEffect::PaddleHit,
+        SoundEffect::WallBounce,
+        SoundEffect::Goal,
+        SoundEffect::Countdown,
+    ];
+
+    /// The sound for a simulation event, if it makes one
+    pub fn for_event(event: &SimEvent) -> Option<SoundEffect> {
+        match event {
+            SimEvent::PaddleHit { .. } | SimEvent::Launch { .. } => Some(SoundEffect::PaddleHit),
+            SimEvent::WallBounce => Some(SoundEffect::WallBounce),
+            SimEvent::Goal { .. } => Some(SoundEffect::Goal),
+            SimEvent::ServeCountdown { .. } | SimEvent::Overtime => Some(SoundEffect::Countdown),
+            // the goal that won it already made a sound
+            SimEvent::Serve { .. } | SimEvent::GameOver { .. } | SimEvent::MatchOver { .. } => None,
+        }
+    }
+
+    // square wave beeps like the arcade machine made
+    fn tone(self) -> (f32, f32) {
+        // (frequency in hz, length in seconds)
+        match self {
+            SoundEffect::PaddleHit => (459.0, 0.06),
+            SoundEffect::WallBounce => (226.0, 0.04),
+            SoundEffect::Goal => (490.0, 0.26),
+            SoundEffect::Countdown => (880.0, 0.1),
+        }
+    }
+}
+
+/// Whatever actually makes the noise. Each effect plays on its own, starting one that's already
+/// playing restarts it
+pub trait AudioBackend {
+    fn play(&mut self, effect: SoundEffect);
+    fn stop(&mut self, effect: SoundEffect);
+}
+
+/// For when there's no audio device, or no one to hear it
+pub struct NullBackend;
+
+impl AudioBackend for NullBackend {
+    fn play(&mut self, _effect: SoundEffect) {}
+    fn stop(&mut self, _effect: SoundEffect) {}
+}
+
+const SAMPLE_RATE: u32 = 44100;
+
+/// A mono 16 bit wav file of `effect`'s beep, faded out at the end so it doesn't click
+fn synthesize_wav(effect: SoundEffect) -> Vec<u8> {
+    let (frequency, length) = effect.tone();
+    let sample_count = (SAMPLE_RATE as f32 * length) as u32;
+    let fade_samples = sample_count / 4;
+
+    let mut wav = Vec::with_capacity(44 + sample_count as usize * 2);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + sample_count * 2).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes()); // size of the format chunk
+    wav.extend_from_slice(&1u16.to_le_bytes()); // pcm
+    wav.extend_from_slice(&1u16.to_le_bytes()); // channels
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // bytes per second
+    wav.extend_from_slice(&2u16.to_le_bytes()); // bytes per sample
+    wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(sample_count * 2).to_le_bytes());
+    for i in 0..sample_count {
+        let phase = (i as f32 * frequency / SAMPLE_RATE as f32).fract();
+        let level = if phase < 0.5 { 1.0 } else { -1.0 };
+        let fade = ((sample_count - i) as f32 / fade_samples as f32).min(1.0);
+        let sample = (level * fade * 0.3 * i16::MAX as f32) as i16;
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+/// Plays through raylib's audio device
+pub struct RaylibBackend {
+    audio: RaylibAudio,
+    // in the order of SoundEffect::ALL
+    sounds: Vec<Sound>,
+}
+
+impl RaylibBackend {
+    pub fn new() -> Result<RaylibBackend, String> {
+        let audio = RaylibAudio::init_audio_device();
+        if !audio.is_audio_device_ready() {
+            return Err(String::from("no audio device"));
+        }
+        let file_type = CString::new(".wav").unwrap();
+        let mut sounds = Vec::with_capacity(SoundEffect::ALL.len());
+        for effect in SoundEffect::ALL.iter() {
+            let wav = synthesize_wav(*effect);
+            // SAFETY: raylib copies the samples out of the buffer, which outlives the call, and
+            // the wave it returns is only used on this thread
+            let wave = unsafe {
+                Wave::from_raw(raylib::ffi::LoadWaveFromMemory(
+                    file_type.as_ptr(),
+                    wav.as_ptr(),
+                    wav.len() as i32,
+                ))
+            };
+            if wave.data.is_null() {
+                return Err(format!("couldn't load the {:?} sound", effect));
+            }
+            sounds.push(Sound::load_sound_from_wave(&wave)?);
+        }
+        Ok(RaylibBackend { audio, sounds })
+    }
+}
+
+impl AudioBackend for RaylibBackend {
+    fn play(&mut self, effect: SoundEffect) {
+        let sound = &self.sounds[effect as usize];
+        self.audio.play_sound(sound);
+    }
+
+    fn stop(&mut self, effect: SoundEffect) {
+        let sound = &self.sounds[effect as usize];
+        self.audio.stop_sound(sound);
+    }
+}
+
+/// The game's one audio backend, shared by every scene that makes sound
+#[derive(Clone)]
+pub struct Audio(Rc<RefCell<Box<dyn AudioBackend>>>);
+
+impl Audio {
+    pub fn new(backend: Box<dyn AudioBackend>) -> Audio {
+        Audio(Rc::new(RefCell::new(backend)))
+    }
+
+    pub fn null() -> Audio {
+        Audio::new(Box::new(NullBackend))
+    }
+
+    pub fn play(&self, effect: SoundEffect) {
+        self.0.borrow_mut().play(effect);
+    }
+
+    pub fn stop(&self, effect: SoundEffect) {
+        self.0.borrow_mut().stop(effect);
+    }
+}
+
+/// Makes the sounds for a match's events. Sounds play as soon as they're predicted so they
+/// line up with what's on screen, and are cut off if a rollback takes the event back
+pub struct SoundPlayer {
+    audio: Audio,
+    // the event each effect last played for, in the order of SoundEffect::ALL
+    last_played: [Option<(u32, SimEvent)>; 4],
+}
+
+impl SoundPlayer {
+    pub fn new(audio: Audio) -> SoundPlayer {
+        SoundPlayer {
+            audio,
+            last_played: [None; 4],
+        }
+    }
+}
+
+impl EventSubscriber for SoundPlayer {
+    fn on_event(&mut self, event: &FrameEvent) {
+        let effect = match SoundEffect::for_event(&event.event) {
+            Some(effect) => effect,
+            None => return,
+        };
+        let last_played = &mut self.last_played[effect as usize];
+        let this_event = Some((event.frame, event.event));
+        match event.status {
+            EventStatus::Predicted => {
+                if *last_played != this_event {
+                    self.audio.play(effect);
+                    *last_played = this_event;
+                }
+            }
+            // if the same effect has played since, that's the one still going
+            EventStatus::Cancelled => {
+                if *last_played == this_event {
+                    self.audio.stop(effect);
+                    *last_played = None;
+                }
+            }
+            // it already played when it was predicted
+            EventStatus::Confirmed => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(PartialEq, Debug)]
+    enum Call {
+        Play(SoundEffect),
+        Stop(SoundEffect),
+    }
+
+    struct RecordingBackend(Rc<RefCell<Vec<Call>>>);
+
+    impl AudioBackend for RecordingBackend {
+        fn play(&mut self, effect: SoundEffect) {
+            self.0.borrow_mut().push(Call::Play(effect));
+        }
+        fn stop(&mut self, effect: SoundEffect) {
+            self.0.borrow_mut().push(Call::Stop(effect));
+        }
+    }
+
+    #[test]
+    fn test_sound_player() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut player = SoundPlayer::new(Audio::new(Box::new(RecordingBackend(calls.clone()))));
+        let mut hear = |frame, event, status| {
+            player.on_event(&FrameEvent {
+                frame,
+                event,
+                status,
+            });
+            calls.borrow_mut().drain(..).collect::<Vec<Call>>()
+        };
+        let hit = SimEvent::PaddleHit {
+            side: 1,
+            speed: 500.0,
+        };
+
+        assert_eq!(
+            hear(5, hit, EventStatus::Predicted),
+            vec![Call::Play(SoundEffect::PaddleHit)]
+        );
+        // heard again or confirmed, it doesn't play twice
+        assert_eq!(hear(5, hit, EventStatus::Predicted), vec![]);
+        assert_eq!(hear(5, hit, EventStatus::Confirmed), vec![]);
+
+        // a rolled back hit is cut off
+        assert_eq!(
+            hear(9, hit, EventStatus::Predicted),
+            vec![Call::Play(SoundEffect::PaddleHit)]
+        );
+        assert_eq!(
+            hear(9, hit, EventStatus::Cancelled),
+            vec![Call::Stop(SoundEffect::PaddleHit)]
+        );
+
+        // but not when it's an older one and a newer hit is what's playing
+        hear(20, hit, EventStatus::Predicted);
+        hear(30, hit, EventStatus::Predicted);
+        assert_eq!(hear(20, hit, EventStatus::Cancelled), vec![]);
+
+        assert_eq!(
+            hear(30, SimEvent::Serve { server: 0 }, EventStatus::Predicted),
+            vec![]
+        );
+        assert_eq!(
+            hear(30, SimEvent::Goal { scorer: 0 }, EventStatus::Predicted),
+            vec![Call::Play(SoundEffect::Goal)]
+        );
+        assert_eq!(
+            hear(
+                90,
+                SimEvent::ServeCountdown { seconds_left: 1 },
+                EventStatus::Predicted
+            ),
+            vec![Call::Play(SoundEffect::Countdown)]
+        );
+    }
+
+    #[test]
+    fn test_synthesize_wav() {
+        let wav = synthesize_wav(SoundEffect::Goal);
+        let data_size = u32::from_le_bytes([wav[40], wav[41], wav[42], wav[43]]);
+        assert_eq!(wav.len(), 44 + data_size as usize);
+        assert_eq!(data_size, (SAMPLE_RATE as f32 * 0.26) as u32 * 2);
+        // faded all the way out
+        assert!(i16::from_le_bytes([wav[wav.len() - 2], wav[wav.len() - 1]]).abs() < 100);
+    }
+}
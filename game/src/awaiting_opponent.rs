@@ -1,24 +1,27 @@
 use raylib::prelude::*;
 
-use std::io;
-use std::io::{Read, Write};
 use std::net::TcpStream;
 
+use tracing::{info, warn};
+
 use crate::imui::*;
 use crate::pong;
 use crate::scene::*;
+use crate::title_screen;
+
+use common::protocol::{Connection, Message};
 
 pub struct AwaitingOpponent {
-    pub lobby_stream: TcpStream,
+    // handed over to the pong game once somebody joins
+    lobby_connection: Option<Connection>,
     pub lobby_code: i32,
     text_to_copy_to_clipboard: Option<String>,
 }
 
 impl AwaitingOpponent {
     pub fn new(stream: TcpStream, lobby_code: i32) -> Self {
-        stream.set_nonblocking(true).unwrap();
         AwaitingOpponent {
-            lobby_stream: stream,
+            lobby_connection: Some(Connection::new(stream).unwrap()),
             lobby_code: lobby_code,
             text_to_copy_to_clipboard: None,
         }
@@ -27,26 +30,46 @@ impl AwaitingOpponent {
 
 impl Scene for AwaitingOpponent {
     fn process(&mut self, _s: &mut SceneAPI, rl: &mut RaylibHandle) {
-        let mut receive_buffer = [0];
-        match self.lobby_stream.read_exact(&mut receive_buffer) {
-            Ok(_) => {
-                if receive_buffer[0] == 1 {
+        let connection = match self.lobby_connection.as_mut() {
+            Some(connection) => connection,
+            None => return, // already switching scenes
+        };
+        // messages are taken out one at a time, whatever arrives after the opponent
+        // joined stays buffered in the connection for the pong game
+        let read_result = connection.read_available();
+        while let Ok(Some(message)) = connection.next_message() {
+            match message {
+                Message::OpponentJoined => {
                     // player joined our lobby!
+                    info!(lobby = self.lobby_code, "Player joined the lobby");
 
                     _s.new_scene = Some(Box::new(pong::PongGame::new(
-                        self.lobby_stream.try_clone().unwrap(),
+                        self.lobby_connection.take().unwrap(),
                         true,
+                        self.lobby_code,
+                        _s.config,
+                        _s.audio.clone(),
                     )));
-                } else {
-                    println!("Wonky thing received from server: {}", receive_buffer[0]);
+                    return;
                 }
-            }
-            Err(e) => match e.kind() {
-                io::ErrorKind::WouldBlock => {}
-                _ => {
-                    println!("Failed to receive data from server: {}", e);
+                Message::ServerShuttingDown { .. } => {
+                    info!(
+                        lobby = self.lobby_code,
+                        "Relay is shutting down, lobby closed"
+                    );
+                    _s.new_scene = Some(Box::new(title_screen::TitleScreen::with_error(
+                        "Server is shutting down, lobby closed",
+                    )));
+                    return;
                 }
-            },
+                _ => warn!(?message, "Wonky thing received from server"),
+            }
+        }
+        if let Err(e) = read_result {
+            warn!(error = %e, "Lost connection to the server");
+            _s.new_scene = Some(Box::new(title_screen::TitleScreen::with_error(
+                "Lost connection to the lobby server",
+            )));
         }
         match &self.text_to_copy_to_clipboard {
             Some(text) => {
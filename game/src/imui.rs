@@ -64,3 +64,37 @@ pub fn button(
 
     d.is_mouse_button_pressed(MouseButton::MOUSE_LEFT_BUTTON) && hovered
 }
+
+/// A horizontal bar filled up to `fraction` of the way across. Returns where along it the
+/// mouse is, from 0 to 1, while the left button is held down over it
+pub fn scrub_bar(
+    d: &mut RaylibDrawHandle,
+    upper_left_corner: Vector2,
+    size: Vector2,
+    fraction: f32,
+) -> Option<f32> {
+    let bounding_box = rect_new_ex(upper_left_corner, size);
+    let mouse_position = d.get_mouse_position();
+    let hovered = bounding_box.check_collision_point_rec(mouse_position);
+
+    d.draw_rectangle_v(
+        rect_pos(&bounding_box),
+        rect_size(&bounding_box),
+        Color::new(170, 170, 170, 255),
+    );
+    d.draw_rectangle_v(
+        upper_left_corner,
+        Vector2::new(size.x * fraction.clamp(0.0, 1.0), size.y),
+        if hovered {
+            Color::DARKGRAY
+        } else {
+            Color::GRAY
+        },
+    );
+
+    if hovered && d.is_mouse_button_down(MouseButton::MOUSE_LEFT_BUTTON) {
+        Some(((mouse_position.x - upper_left_corner.x) / size.x).clamp(0.0, 1.0))
+    } else {
+        None
+    }
+}
@@ -1,8 +1,17 @@
 // scenes - these effectively act as separate games
 pub mod awaiting_opponent;
 pub mod pong; // pong game logic, ui, and rollback networking
+pub mod replay_viewer; // plays back a recorded match
+pub mod results; // who won, once a match is over
+pub mod spectator; // watches a running match without playing in it
 pub mod title_screen; // title screen buttons and scene switching logic // screen that polls the server waiting for an opponent to join
 
 // utility functions - these are more like libraries
+pub mod audio; // sound effects, and the backends that play them
 pub mod imui;
+pub mod net_thread; // sends and receives for the match off the main thread
+pub mod reconnect; // gets back into a match after the connection to the relay drops
 pub mod scene; // scene API and scene struct/trait // immediate mode ui
+pub mod sim_events; // what happened in the match, taking rollbacks into account
+pub mod smoothing; // eases what's drawn over rollback corrections
+pub mod timestep; // runs the simulation at a fixed rate whatever the frame rate
@@ -1,22 +1,53 @@
 use std::env;
+use std::path::Path;
+
+use tracing::warn;
+use tracing_subscriber::EnvFilter;
+
+use common::config::CONFIG_FILE;
+use common::sim::{PongGameConfig, GAME_CONFIG};
 
 // scenes - these effectively act as separate games
 pub mod awaiting_opponent;
 pub mod pong; // pong game logic, ui, and rollback networking
+pub mod replay_viewer; // plays back a recorded match
+pub mod results; // who won, once a match is over
+pub mod spectator; // watches a running match without playing in it
 pub mod title_screen; // title screen buttons and scene switching logic // screen that polls the server waiting for an opponent to join
 
 // utility functions - these are more like libraries
+pub mod audio; // sound effects, and the backends that play them
 pub mod imui;
+pub mod net_thread; // sends and receives for the match off the main thread
+pub mod reconnect; // gets back into a match after the connection to the relay drops
 pub mod scene; // scene API and scene struct/trait // immediate mode ui
+pub mod sim_events; // what happened in the match, taking rollbacks into account
+pub mod smoothing; // eases what's drawn over rollback corrections
+pub mod timestep; // runs the simulation at a fixed rate whatever the frame rate
 
 use scene::*;
 
+const MAX_RENDER_FPS: u32 = 240;
+
 fn main() {
-    let window_width = pong::GAME_CONFIG.arena_size.x as i32;
+    // RUST_LOG filters by module and level, e.g. RUST_LOG=game::pong=debug
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let config = PongGameConfig::load(Path::new(CONFIG_FILE)).unwrap_or_else(|e| {
+        warn!(error = %e, file = CONFIG_FILE, "Ignoring the game settings, using the defaults");
+        GAME_CONFIG
+    });
+
+    let window_width = config.arena_size.x as i32;
 
     let (mut rl, thread) = raylib::init()
-        .size(window_width, pong::GAME_CONFIG.arena_size.y as i32)
+        .size(window_width, config.arena_size.y as i32)
         .title("Rust Pong")
+        .vsync()
         .build();
 
     // easier development forcing the window to go to the right or the left
@@ -30,14 +61,27 @@ fn main() {
                 rl.get_window_position().y as i32,
             );
         } else {
-            println!("unknown argument {}", args[1]);
+            warn!(argument = %args[1], "Unknown argument");
         }
     }
 
-    rl.set_target_fps(60);
+    // the simulation keeps its own time, this only stops drawing far more often than the
+    // display can show when vsync is off
+    rl.set_target_fps(MAX_RENDER_FPS);
 
     let mut cur_scene: Box<dyn Scene> = Box::new(title_screen::TitleScreen::new());
-    let mut scene_api = SceneAPI { new_scene: None };
+    let audio = match audio::RaylibBackend::new() {
+        Ok(backend) => audio::Audio::new(Box::new(backend)),
+        Err(e) => {
+            warn!(error = %e, "Couldn't start audio, playing without sound");
+            audio::Audio::null()
+        }
+    };
+    let mut scene_api = SceneAPI {
+        new_scene: None,
+        audio,
+        config,
+    };
 
     while !rl.window_should_close() {
         cur_scene.process(&mut scene_api, &mut rl);
@@ -0,0 +1,149 @@
+use std::io;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use common::protocol::{Connection, Message};
+
+// how long the thread waits for something to send before checking the socket again. Anything
+// to send wakes it straight away
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A message from the relay and when it came off the socket, which can be a while before the
+/// game gets around to it if a frame is slow
+pub struct Received {
+    pub message: Message,
+    pub at: Instant,
+}
+
+/// Talks to the relay on its own thread so a slow frame or a dragged window doesn't hold up
+/// sending our input or reading the opponent's. Dropping it closes the connection
+pub struct NetThread {
+    outgoing: mpsc::Sender<Message>,
+    incoming: mpsc::Receiver<io::Result<Received>>,
+}
+
+impl NetThread {
+    /// Takes over the connection, including anything already buffered in it
+    pub fn start(mut connection: Connection) -> NetThread {
+        let (outgoing_tx, outgoing_rx) = mpsc::channel::<Message>();
+        let (incoming_tx, incoming_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let error = loop {
+                match outgoing_rx.recv_timeout(POLL_INTERVAL) {
+                    Ok(message) => {
+                        if let Err(e) = connection.send(&message) {
+                            break e;
+                        }
+                        continue;
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    // the game is done with the connection
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+                // whatever the socket wouldn't take last time
+                if let Err(e) = connection.flush() {
+                    break e;
+                }
+                let (messages, error) = connection.receive();
+                let at = Instant::now();
+                for message in messages {
+                    incoming_tx
+                        .send(Ok(Received { message, at }))
+                        .unwrap_or_default();
+                }
+                if let Some(e) = error {
+                    break e;
+                }
+            };
+            debug!(error = %error, "Network thread stopped");
+            incoming_tx.send(Err(error)).unwrap_or_default();
+        });
+        NetThread {
+            outgoing: outgoing_tx,
+            incoming: incoming_rx,
+        }
+    }
+
+    /// Hands the message to the network thread, which sends it right away
+    pub fn send(&self, message: &Message) -> io::Result<()> {
+        self.outgoing
+            .send(*message)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "network thread stopped"))
+    }
+
+    /// Every message received since the last call, oldest first. Like Connection::receive, if
+    /// the connection failed the error comes back after the messages that made it through
+    pub fn receive(&self) -> (Vec<Received>, Option<io::Error>) {
+        let mut received = Vec::new();
+        loop {
+            match self.incoming.try_recv() {
+                Ok(Ok(message)) => received.push(message),
+                Ok(Err(e)) => return (received, Some(e)),
+                Err(mpsc::TryRecvError::Empty) => return (received, None),
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    let e = io::Error::new(io::ErrorKind::BrokenPipe, "network thread stopped");
+                    return (received, Some(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::PongInputState;
+    use std::net::{TcpListener, TcpStream};
+
+    fn receive_until(net: &NetThread, count: usize) -> (Vec<Received>, Option<io::Error>) {
+        let mut all = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            let (received, error) = net.receive();
+            all.extend(received);
+            if all.len() >= count || error.is_some() {
+                return (all, error);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        (all, None)
+    }
+
+    #[test]
+    fn test_net_thread() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut relay = Connection::new(listener.accept().unwrap().0).unwrap();
+        let net = NetThread::start(Connection::new(stream).unwrap());
+
+        let input = PongInputState::new(3, -0.5);
+        net.send(&Message::Input(input)).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut messages = Vec::new();
+        while messages.is_empty() && Instant::now() < deadline {
+            messages = relay.receive().0;
+        }
+        assert_eq!(messages, vec![Message::Input(input)]);
+
+        let before = Instant::now();
+        relay.send(&Message::OpponentReconnected).unwrap();
+        relay.send(&Message::Input(input)).unwrap();
+        let (received, error) = receive_until(&net, 2);
+        assert!(error.is_none());
+        let messages: Vec<Message> = received.iter().map(|r| r.message).collect();
+        assert_eq!(
+            messages,
+            vec![Message::OpponentReconnected, Message::Input(input)]
+        );
+        assert!(received[0].at >= before);
+
+        // the relay going away comes through as an error
+        drop(relay);
+        let (received, error) = receive_until(&net, 1);
+        assert!(received.is_empty());
+        assert!(error.is_some());
+    }
+}
@@ -2,36 +2,44 @@ use crate::scene::*;
 
 use raylib::prelude::*;
 
-use std::io;
-use std::io::{Read, Write};
-use std::net::TcpStream;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
 use std::vec::Vec;
 
-use common::PongInputState;
-
-pub const GAME_CONFIG: PongGameConfig = PongGameConfig {
-    arena_size: Vector2::new(1000.0, 800.0),
-    paddle_size: Vector2::new(25.0, 175.0),
-    paddle_force: 1000.0,
-    paddle_friction: 300.0,
-    ball_size: 20.0,
-    ball_speed: 400.0,
-    score_font_size: 80,
-    max_rollback_frames: 128,
-    dt: 1.0 / 60.0,
+use tracing::{info, info_span, trace, warn};
+
+use crate::audio::{Audio, SoundEffect, SoundPlayer};
+use crate::net_thread::{NetThread, Received};
+use crate::reconnect;
+use crate::results;
+use crate::sim_events::EventStream;
+use crate::smoothing::Smoothing;
+use crate::timestep::FixedTimestep;
+use crate::title_screen;
+use common::latency::{Heartbeat, RttEstimator, HEARTBEAT_TIMEOUT};
+use common::protocol::{Connection, Message, ReconnectRequest};
+use common::replay::{unix_millis, Replay};
+use common::sim::{
+    self, Ball, MatchPhase, Paddle, PongGameConfig, PongGameState, Score, ServePhase, SimEvent,
 };
+use common::validation::{validate_input, InputViolation};
+use common::PongInputState;
 
-pub struct PongGameConfig {
-    pub arena_size: Vector2,
-    paddle_size: Vector2,
-    paddle_force: f32,
-    paddle_friction: f32,
-    ball_size: f32,
-    ball_speed: f32,
-    score_font_size: i32,
-    max_rollback_frames: usize, // must be a power of 2 for RingBuffer
-    dt: f32,
-}
+// enough to cover everything sent since the relay last heard from us
+const SENT_INPUTS_LEN: usize = 512;
+// how often a snapshot of the game goes to the relay for spectators who join late
+const SNAPSHOT_INTERVAL_FRAMES: u32 = 60;
+// invalid inputs from the opponent that are put up with before the match is ended
+const MAX_REMOTE_INPUT_VIOLATIONS: u32 = 5;
+// every match is saved here when it ends, relative to where the game was started from
+pub const REPLAY_DIR: &str = "replays";
+// how much of a rollback correction is made up each frame when drawing, and how big one has
+// to be to snap instead
+const SMOOTHING_BLEND: f32 = 0.3;
+const SMOOTHING_SNAP_DISTANCE: f32 = 150.0;
 
 fn key_strength(rl: &RaylibHandle, key: KeyboardKey) -> f32 {
     if rl.is_key_down(key) {
@@ -49,209 +57,97 @@ fn dimension_strength(
     key_strength(rl, positive_key) - key_strength(rl, negative_key)
 }
 
-#[derive(PartialEq, Debug, Clone)]
-struct Paddle {
-    position: Vector2,
-    velocity: f32,
-    on_left_side: bool,
-}
-
-impl Paddle {
-    fn new(on_left_side: bool) -> Paddle {
-        let pos = if on_left_side {
-            Vector2::new(0.0, 0.0)
-        } else {
-            Vector2::new(GAME_CONFIG.arena_size.x - GAME_CONFIG.paddle_size.x, 0.0)
-        };
-
-        Paddle {
-            position: pos,
-            velocity: 0.0,
-            on_left_side: on_left_side,
-        }
-    }
-    fn process_movement(&mut self, vertical_input: f32, dt: f32) {
-        self.velocity +=
-            vertical_input * (GAME_CONFIG.paddle_force + GAME_CONFIG.paddle_friction) * dt;
-        let friction_effect = -self.velocity.signum() * GAME_CONFIG.paddle_friction * dt;
-        if self.velocity.abs() < friction_effect.abs() {
-            self.velocity = 0.0;
-        } else {
-            self.velocity += friction_effect;
-        }
-        self.position.y += self.velocity * dt;
-        if self.position.y <= 0.0
-            || self.position.y + GAME_CONFIG.paddle_size.y >= GAME_CONFIG.arena_size.y
-        {
-            self.velocity *= -1.0;
-        }
-    }
-    fn get_ball_hit_x(&self) -> f32 {
-        if self.on_left_side {
-            self.position.x + GAME_CONFIG.paddle_size.x + GAME_CONFIG.ball_size
-        } else {
-            self.position.x - GAME_CONFIG.ball_size
-        }
-    }
-    fn ball_overlaps(&self, ball: &Ball) -> bool {
-        let local_ball_pos = ball.position - self.position;
-
-        (local_ball_pos.x >= -GAME_CONFIG.ball_size
-            && local_ball_pos.x <= GAME_CONFIG.paddle_size.x + GAME_CONFIG.ball_size)
-            && (local_ball_pos.y >= -GAME_CONFIG.ball_size
-                && local_ball_pos.y <= GAME_CONFIG.paddle_size.y + GAME_CONFIG.ball_size)
-    }
-    fn draw(&self, d: &mut RaylibDrawHandle) {
-        d.draw_rectangle_v(self.position, GAME_CONFIG.paddle_size, Color::BLACK);
-    }
+/// Drawing for the simulation types, which live in common so they can't know about raylib
+pub(crate) trait Draw {
+    fn draw(&self, d: &mut RaylibDrawHandle);
 }
 
-#[derive(PartialEq, Debug, Clone)]
-struct Ball {
-    position: Vector2,
-    movement: Vector2,
-    increased_speed: f32,
+fn to_raylib(v: sim::Vector2) -> Vector2 {
+    Vector2::new(v.x, v.y)
 }
 
-impl Ball {
-    fn new(horizontal_multiplier: f32) -> Ball {
-        let mut to_return = Ball {
-            position: Vector2::new(0.0, 0.0),
-            movement: Vector2::new(1.0 * horizontal_multiplier, 0.0),
-            increased_speed: 0.0,
-        };
-        to_return.reset();
-
-        to_return
-    }
-
-    fn reset(&mut self) {
-        self.position = GAME_CONFIG.arena_size / 2.0;
-        self.movement = Vector2::new(self.movement.x * -1.0, 0.0).normalized();
-        self.increased_speed = 0.0;
+/// Resizes the window to the arena, which the host's settings might have made bigger or
+/// smaller than the one it was opened for
+pub(crate) fn fit_window(rl: &mut RaylibHandle, config: &PongGameConfig) {
+    let (width, height) = (config.arena_size.x as i32, config.arena_size.y as i32);
+    if rl.get_screen_width() != width || rl.get_screen_height() != height {
+        rl.set_window_size(width, height);
     }
+}
 
-    fn draw(&self, d: &mut RaylibDrawHandle) {
-        d.draw_circle_v(self.position, GAME_CONFIG.ball_size, Color::RED);
-    }
-
-    /// Moves along the movement vector and bounces on paddles
-    fn process_movement(&mut self, dt: f32, left_paddle: &Paddle, right_paddle: &Paddle) {
-        // bounce off of paddles
-        let paddles = [left_paddle, right_paddle];
-        for paddle in paddles.iter() {
-            if paddle.ball_overlaps(self) {
-                self.movement.x *= -2.0;
-                self.position.x = paddle.get_ball_hit_x();
-                if paddle.velocity.abs() > 0.01 {
-                    self.movement.y += paddle.velocity.signum();
-                }
-                self.movement.normalize();
-            }
-        }
-
-        // bounce off of top and bottom walls
-        if self.position.y <= GAME_CONFIG.ball_size {
-            self.movement.y *= -1.0;
-            self.position.y = GAME_CONFIG.ball_size;
-        }
-        if self.position.y >= GAME_CONFIG.arena_size.y - GAME_CONFIG.ball_size {
-            self.movement.y *= -1.0;
-            self.position.y = GAME_CONFIG.arena_size.y - GAME_CONFIG.ball_size;
-        }
-
-        // move and increase speed over time
-        self.position += self.movement * dt * (GAME_CONFIG.ball_speed + self.increased_speed);
-        self.increased_speed += dt * 50.0;
-    }
+// the pieces of the game are sized by the match's settings, which only the state knows
+fn draw_paddle(paddle: &Paddle, config: &PongGameConfig, d: &mut RaylibDrawHandle) {
+    d.draw_rectangle_v(
+        to_raylib(paddle.position),
+        to_raylib(config.paddle_size),
+        Color::BLACK,
+    );
 }
 
-#[derive(PartialEq, Debug, Clone)]
-struct Score {
-    value: i32,
-    left_side: bool,
+fn draw_ball(ball: &Ball, config: &PongGameConfig, d: &mut RaylibDrawHandle) {
+    d.draw_circle_v(to_raylib(ball.position), config.ball_size, Color::RED);
 }
 
-impl Score {
-    fn new(on_left_side: bool) -> Score {
-        Score {
-            value: 0,
-            left_side: on_left_side,
-        }
+fn draw_score(score: &Score, config: &PongGameConfig, d: &mut RaylibDrawHandle) {
+    let score_string = score.value.to_string();
+    let to_draw_middle_x;
+    if score.left_side {
+        to_draw_middle_x = config.arena_size.x / 4.0;
+    } else {
+        to_draw_middle_x = (3.0 * config.arena_size.x) / 4.0;
     }
+    d.draw_text(
+        &score_string,
+        to_draw_middle_x as i32 - (measure_text(&score_string, config.score_font_size) / 2),
+        20,
+        config.score_font_size,
+        Color::BLACK,
+    );
+}
 
+impl Draw for PongGameState {
     fn draw(&self, d: &mut RaylibDrawHandle) {
-        let score_string = self.value.to_string();
-        let to_draw_middle_x;
-        if self.left_side {
-            to_draw_middle_x = GAME_CONFIG.arena_size.x / 4.0;
-        } else {
-            to_draw_middle_x = (3.0 * GAME_CONFIG.arena_size.x) / 4.0;
+        let config = &self.config;
+        draw_paddle(&self.left_paddle, config, d);
+        draw_paddle(&self.right_paddle, config, d);
+        draw_ball(&self.ball, config, d);
+        draw_score(&self.left_score, config, d);
+        draw_score(&self.right_score, config, d);
+
+        let mut status = Vec::new();
+        if config.rules.best_of > 1 {
+            status.push(format!(
+                "GAMES {} - {}",
+                self.games_won[0], self.games_won[1]
+            ));
+        }
+        if self.phase == MatchPhase::Overtime {
+            status.push(String::from("SUDDEN DEATH"));
+        } else if let Some(time_limit) = config.rules.time_limit_frames {
+            let seconds_left = config.seconds_left(time_limit.saturating_sub(self.game_frames));
+            status.push(format!("{}:{:02}", seconds_left / 60, seconds_left % 60));
         }
+        let status = status.join("   ");
+        let font_size = 30;
         d.draw_text(
-            &score_string,
-            to_draw_middle_x as i32
-                - (measure_text(&score_string, GAME_CONFIG.score_font_size) / 2),
-            20,
-            GAME_CONFIG.score_font_size,
-            Color::BLACK,
+            &status,
+            (config.arena_size.x as i32 - measure_text(&status, font_size)) / 2,
+            30,
+            font_size,
+            Color::DARKGRAY,
         );
-    }
-}
 
-#[derive(PartialEq, Debug, Clone)]
-struct PongGameState {
-    left_paddle: Paddle,
-    right_paddle: Paddle,
-    ball: Ball,
-    left_score: Score,
-    right_score: Score,
-}
-
-impl PongGameState {
-    fn new() -> PongGameState {
-        PongGameState {
-            left_paddle: Paddle::new(true),
-            right_paddle: Paddle::new(false),
-            ball: Ball::new(1.0),
-            left_score: Score::new(true),
-            right_score: Score::new(false),
-        }
-    }
-
-    fn process_paddle_input(&mut self, i: f32, is_on_left_side: bool) {
-        (if is_on_left_side {
-            &mut self.left_paddle
-        } else {
-            &mut self.right_paddle
-        })
-        .process_movement(i, GAME_CONFIG.dt);
-    }
-
-    fn process_logic(&mut self, inputs: &[PongInputState; 2]) {
-        self.process_paddle_input(inputs[0].input, true);
-        self.process_paddle_input(inputs[1].input, false);
-
-        let dt = GAME_CONFIG.dt;
-        self.ball
-            .process_movement(dt, &self.left_paddle, &self.right_paddle);
-        if self.ball.position.x <= -GAME_CONFIG.ball_size {
-            self.right_score.value += 1;
-            self.ball.reset();
-        }
-        if self.ball.position.x >= GAME_CONFIG.arena_size.x + GAME_CONFIG.ball_size {
-            self.left_score.value += 1;
-            self.ball.reset();
+        if let ServePhase::Countdown { frames_left } = self.serve_phase {
+            let countdown = config.seconds_left(frames_left).to_string();
+            d.draw_text(
+                &countdown,
+                (config.arena_size.x as i32 - measure_text(&countdown, font_size * 3)) / 2,
+                (config.arena_size.y as i32 - font_size * 3) / 2,
+                font_size * 3,
+                Color::LIGHTGRAY,
+            );
         }
     }
-
-    fn draw(&self, d: &mut RaylibDrawHandle) {
-        self.left_paddle.draw(d);
-        self.right_paddle.draw(d);
-        self.ball.draw(d);
-        self.left_score.draw(d);
-        self.right_score.draw(d);
-    }
 }
 
 struct DebugGraph {
@@ -304,7 +200,7 @@ impl DebugGraph {
         // all this because the float might be infinity or nan
         let max_data = self.data.iter().fold(0.0 as f32, |a, &b| a.max(b));
         let min_data = self.data.iter().fold(f32::INFINITY, |a, &b| a.min(b));
-        println!("Max: {}", max_data);
+        trace!(max_data, "Drawing debug graph");
         while cur_point_index > 0 {
             let cur_point = self.data[cur_point_index];
             let next_point = self.data[cur_point_index - 1];
@@ -347,9 +243,23 @@ impl DebugGraph {
     }
 }
 
+fn describe_rtt(name: &str, rtt: &RttEstimator) -> String {
+    match (rtt.rtt(), rtt.jitter()) {
+        (Some(rtt), Some(jitter)) => format!(
+            "{} rtt {}ms jitter {}ms",
+            name,
+            rtt.as_millis(),
+            jitter.as_millis()
+        ),
+        _ => format!("{} rtt ?", name),
+    }
+}
+
 struct PongInputAndGameState {
     player_inputs: [PongInputState; 2], // 0 is left, 1 is right
     game_after_inputs: PongGameState,
+    // what happened while simulating the frame
+    events: Vec<SimEvent>,
 }
 
 pub struct PongGame {
@@ -357,72 +267,493 @@ pub struct PongGame {
     // TODO choose a different datastructure for this that does not have O(n) insert time...
     last_frames: Vec<PongInputAndGameState>, // This vector should always be guaranteed to have something in it, the initial state of the game
     future_inputs: Vec<PongInputState>,
-    opponent_stream: TcpStream,
+    relay_connection: NetThread,
     playing_on_left_side: bool,
+    connection_lost: bool,
+    server_shutdown_deadline: Option<Instant>,
+
+    // reconnect info
+    lobby_code: i32,
+    relay_addr: Option<SocketAddr>,
+    session_token: Option<u64>,
+    // the first remote frame whose input hasn't arrived yet
+    next_remote_frame: u32,
+    // local inputs the relay might not have, resent after a reconnect. Oldest first
+    sent_inputs: VecDeque<PongInputState>,
+    reconnecting: Option<mpsc::Receiver<Result<Connection, String>>>,
+    opponent_reconnect_deadline: Option<Instant>,
+
+    // pinged separately, the relay answers its own pings but peer pings go through it to the opponent
+    relay_heartbeat: Heartbeat,
+    peer_heartbeat: Heartbeat,
+
+    // inputs from the opponent that had to be fixed up or thrown away
+    remote_input_violations: u32,
+
+    // every frame both players' inputs are known for, saved when the match ends
+    replay: Replay,
+    // the confirmed frame someone won the match on, it can't be taken back by a rollback
+    final_state: Option<PongGameState>,
+
+    // nothing is simulated until the relay says what the match is seeded with
+    seeded: bool,
+    // the settings the match is played with, the host's. Nothing is simulated until both
+    // players are known to be using them
+    config: PongGameConfig,
+    configured: bool,
+
+    pub events: EventStream,
+    audio: Audio,
+    // seconds left on whichever countdown is on screen, beeped every time it goes down
+    countdown_seconds: Option<u64>,
+
+    // where things are drawn, eased over rollback corrections
+    smoothing: Smoothing,
+    // frames are simulated at config.dt however fast they're drawn
+    timestep: FixedTimestep,
+    // false while waiting on the opponent
+    simulating: bool,
 
     // debug info
     frames_rolled_back: DebugGraph,
+    log_span: tracing::Span,
 }
 
 impl PongGame {
     // is_host: the host is the left paddle, joiner is the right
-    pub fn new(opponent_stream: TcpStream, is_host: bool) -> PongGame {
-        opponent_stream.set_nonblocking(true).unwrap();
-        PongGame {
+    // config: the settings this player loaded, the joiner's are replaced by the host's
+    pub fn new(
+        relay_connection: Connection,
+        is_host: bool,
+        lobby_code: i32,
+        config: PongGameConfig,
+        audio: Audio,
+    ) -> PongGame {
+        let relay_addr = relay_connection.stream().peer_addr().ok();
+        let log_span = info_span!(
+            "match",
+            lobby = lobby_code,
+            host = is_host,
+            relay = %relay_addr.map(|a| a.to_string()).unwrap_or_default()
+        );
+        let mut events = EventStream::new();
+        events.subscribe(Box::new(SoundPlayer::new(audio.clone())));
+        let mut game = PongGame {
             cur_frame: 0,
             last_frames: vec![PongInputAndGameState {
-                player_inputs: [PongInputState::new(), PongInputState::new()],
-                game_after_inputs: PongGameState::new(),
+                player_inputs: [PongInputState::new(0, 0.0), PongInputState::new(0, 0.0)],
+                game_after_inputs: PongGameState::with_config(config, 0),
+                events: Vec::new(),
             }],
             future_inputs: Vec::new(),
             playing_on_left_side: is_host,
-            opponent_stream: opponent_stream,
+            relay_connection: NetThread::start(relay_connection),
+            connection_lost: false,
+            server_shutdown_deadline: None,
+            lobby_code,
+            relay_addr,
+            session_token: None,
+            next_remote_frame: 0,
+            sent_inputs: VecDeque::with_capacity(SENT_INPUTS_LEN),
+            reconnecting: None,
+            opponent_reconnect_deadline: None,
+            relay_heartbeat: Heartbeat::new(Instant::now()),
+            peer_heartbeat: Heartbeat::new(Instant::now()),
+            remote_input_violations: 0,
+            replay: Replay {
+                lobby_code,
+                started_at: unix_millis(SystemTime::now()),
+                // for when the window is closed mid match, the other ways out set their own
+                end_reason: String::from("left the match"),
+                config,
+                ..Replay::default()
+            },
+            final_state: None,
+            seeded: false,
+            config,
+            // the host's settings are the ones played with, it only has to hear the joiner agrees
+            configured: false,
+            events,
+            audio,
+            countdown_seconds: None,
+            smoothing: Smoothing::new(SMOOTHING_BLEND, SMOOTHING_SNAP_DISTANCE),
+            timestep: FixedTimestep::new(config.dt),
+            simulating: true,
             frames_rolled_back: DebugGraph::new(130),
+            log_span,
+        };
+        if is_host {
+            game.send(&Message::MatchConfig { config });
         }
+        game
     }
 }
 
-impl Scene for PongGame {
-    fn draw(&mut self, _s: &mut SceneAPI, d: &mut RaylibDrawHandle) {
-        d.clear_background(Color::WHITE);
-        self.last_frames[0].game_after_inputs.draw(d);
+impl PongGame {
+    pub fn relay_rtt(&self) -> &RttEstimator {
+        &self.relay_heartbeat.rtt
+    }
 
-        // debug drawing
-        self.frames_rolled_back
-            .draw(d, Vector2::new(20.0, 20.0), Vector2::new(100.0, 70.0));
+    pub fn peer_rtt(&self) -> &RttEstimator {
+        &self.peer_heartbeat.rtt
     }
 
-    fn process(&mut self, _s: &mut SceneAPI, rl: &mut RaylibHandle) {
-        // construct local input from keys pressed
-        let local_input = PongInputState {
-            frame: self.cur_frame,
-            input: dimension_strength(&rl, KeyboardKey::KEY_S, KeyboardKey::KEY_W),
+    /// Frames of input delay that would hide the latency to the opponent
+    pub fn suggested_input_delay(&self) -> u32 {
+        self.peer_rtt()
+            .input_delay_frames(Duration::from_secs_f32(self.config.dt))
+    }
+
+    /// How many frames the game runs ahead of the last input from the opponent before it
+    /// waits for them, past this their next input couldn't be rolled back to
+    fn max_frames_ahead(&self) -> u32 {
+        self.config.max_rollback_frames as u32 - 2
+    }
+
+    fn send(&mut self, message: &Message) {
+        if let Err(e) = self.relay_connection.send(message) {
+            self.lose_connection(&e.to_string());
+        }
+    }
+
+    fn send_heartbeats(&mut self) {
+        if self.connection_lost {
+            return;
+        }
+        let now = Instant::now();
+        if self.relay_heartbeat.timed_out(now) {
+            self.lose_connection("heartbeat timed out");
+            return;
+        }
+        if let Some(sent_at) = self.relay_heartbeat.ping_due(now) {
+            self.send(&Message::Ping { sent_at });
+        }
+        if let Some(sent_at) = self.peer_heartbeat.ping_due(now) {
+            self.send(&Message::PeerPing { sent_at });
+        }
+    }
+
+    /// The relay only notices a dead opponent once their connection times out, this shows it sooner
+    fn opponent_not_responding(&self) -> bool {
+        self.opponent_reconnect_deadline.is_none()
+            && self.peer_heartbeat.quiet_for(Instant::now()) >= HEARTBEAT_TIMEOUT
+    }
+
+    /// Sends the state after the newest frame both players' inputs are known for. Spectators
+    /// who join late start from it
+    fn send_snapshot(&mut self) {
+        let confirmed_frames = self.cur_frame.min(self.next_remote_frame);
+        if confirmed_frames == 0 {
+            return;
+        }
+        let frame = confirmed_frames - 1;
+        let index = (self.cur_frame - 1 - frame) as usize;
+        if let Some(confirmed) = self.last_frames.get(index) {
+            let state = confirmed.game_after_inputs.to_bytes();
+            self.send(&Message::Snapshot { frame, state });
+        }
+    }
+
+    fn send_input(&mut self, input: PongInputState) {
+        self.sent_inputs.push_back(input);
+        if self.sent_inputs.len() > SENT_INPUTS_LEN {
+            self.sent_inputs.pop_front();
+        }
+        if self.connection_lost {
+            // resent once reconnected
+            return;
+        }
+        self.send(&Message::Input(input));
+    }
+
+    /// Makes sure an input from the opponent can't break the simulation or the rollback. Values
+    /// that aren't possible are fixed up so the frame still lines up, frames that don't fit are
+    /// dropped
+    fn check_remote_input(&mut self, mut input: PongInputState) -> Option<PongInputState> {
+        let newest_allowed = self.cur_frame + self.config.max_frames_ahead_of_opponent();
+        let violation = match validate_input(&input, self.next_remote_frame, newest_allowed) {
+            Ok(()) => return Some(input),
+            Err(violation) => violation,
         };
-        self.opponent_stream.write(&local_input.into_u8()).unwrap();
 
-        // TODO continually check the opponent stream until there are no more input state
-        // chunks to process
+        self.remote_input_violations += 1;
+        warn!(
+            %violation,
+            ?input,
+            violations = self.remote_input_violations,
+            "Opponent sent an invalid input"
+        );
+        match violation {
+            InputViolation::NotFinite => {
+                input.input = 0.0;
+                Some(input)
+            }
+            InputViolation::OutOfRange => {
+                input.input = input.input.clamp(-1.0, 1.0);
+                Some(input)
+            }
+            InputViolation::UnknownButtons { .. } => {
+                input.buttons &= PongInputState::ALL_BUTTONS;
+                Some(input)
+            }
+            InputViolation::FrameNotMonotonic { .. } | InputViolation::FrameTooFarAhead { .. } => {
+                None
+            }
+        }
+    }
 
-        // fetch all input states available
-        let mut remote_input_data = PongInputState::new().into_u8();
+    /// Handles every message that has arrived from the relay, returning the opponent's inputs
+    fn receive_messages(&mut self, s: &mut SceneAPI) -> Vec<PongInputState> {
         let mut remote_inputs: Vec<PongInputState> = Vec::new();
-        loop {
-            match self.opponent_stream.read_exact(&mut remote_input_data) {
-                Ok(_) => {
-                    // println!("Received input state, processing input...");
-                    remote_inputs.push(unsafe { PongInputState::from_u8(remote_input_data) });
+        if self.connection_lost {
+            return remote_inputs;
+        }
+        let (messages, error) = self.relay_connection.receive();
+        if let Some(newest) = messages.last() {
+            self.relay_heartbeat.heard_from(newest.at);
+        }
+        for Received { message, at: now } in messages {
+            match message {
+                Message::Input(remote_input) => {
+                    self.peer_heartbeat.heard_from(now);
+                    if let Some(remote_input) = self.check_remote_input(remote_input) {
+                        self.next_remote_frame = remote_input.frame + 1;
+                        remote_inputs.push(remote_input);
+                    }
                 }
-                Err(e) => {
-                    match e.kind() {
-                        io::ErrorKind::WouldBlock => {}
-                        _ => {
-                            println!("Failed to receive data from server: {}", e);
+                Message::ServerShuttingDown { seconds_left } => {
+                    info!(seconds_left, "Relay is shutting down");
+                    self.server_shutdown_deadline =
+                        Some(Instant::now() + Duration::from_secs(seconds_left as u64));
+                }
+                Message::SessionToken { token } => self.session_token = Some(token),
+                Message::MatchSeed { seed } if !self.seeded => {
+                    info!(seed, "Match seeded");
+                    self.replay.seed = seed;
+                    self.seeded = true;
+                    self.restart_from_settings();
+                }
+                Message::MatchConfig { config } if !self.playing_on_left_side => {
+                    if !self.configured {
+                        if let Err(e) = config.validate() {
+                            warn!(error = %e, "Host sent unusable game settings");
+                            self.end_match(s, &format!("Host's game settings are invalid: {}", e));
+                            return Vec::new();
                         }
+                        info!(
+                            hash = config.config_hash(),
+                            "Playing with the host's game settings"
+                        );
+                        self.config = config;
+                        self.configured = true;
+                        self.timestep = FixedTimestep::new(config.dt);
+                        self.restart_from_settings();
+                    }
+                    // answered every time, the relay resends the settings after a reconnect
+                    // in case the answer was lost
+                    let hash = self.config.config_hash();
+                    self.send(&Message::ConfigHash { hash });
+                }
+                Message::ConfigHash { hash } if self.playing_on_left_side => {
+                    if hash != self.config.config_hash() {
+                        warn!(
+                            hash,
+                            expected = self.config.config_hash(),
+                            "Opponent is playing with different game settings"
+                        );
+                        self.end_match(s, "Opponent's game settings don't match yours");
+                        return Vec::new();
+                    }
+                    if !self.configured {
+                        info!(hash, "Opponent agreed on the game settings");
+                        self.configured = true;
                     }
-                    break;
                 }
+                Message::OpponentDisconnected { grace_seconds } => {
+                    info!(grace_seconds, "Opponent disconnected");
+                    self.opponent_reconnect_deadline =
+                        Some(Instant::now() + Duration::from_secs(grace_seconds as u64));
+                }
+                Message::OpponentReconnected => {
+                    info!("Opponent reconnected");
+                    self.opponent_reconnect_deadline = None;
+                    self.peer_heartbeat.heard_from(now);
+                }
+                Message::Ping { sent_at } => self.send(&Message::Pong { sent_at }),
+                Message::Pong { sent_at } => {
+                    self.relay_heartbeat.pong_received(sent_at, now);
+                }
+                Message::PeerPing { sent_at } => {
+                    self.peer_heartbeat.heard_from(now);
+                    self.send(&Message::PeerPong { sent_at });
+                }
+                Message::PeerPong { sent_at } => {
+                    self.peer_heartbeat.pong_received(sent_at, now);
+                }
+                Message::OpponentLeft => {
+                    info!("Opponent didn't reconnect, match over");
+                    self.end_match(s, "Opponent left the match");
+                }
+                Message::MatchResult {
+                    frame,
+                    left_score,
+                    right_score,
+                } => {
+                    info!(frame, left_score, right_score, "Relay ended the match");
+                    self.end_match(
+                        s,
+                        &format!(
+                            "Match over, official score {} - {}",
+                            left_score, right_score
+                        ),
+                    );
+                    // the relay closes the connection next, nothing after the result matters
+                    return remote_inputs;
+                }
+                Message::Resumed {
+                    next_frame_expected,
+                } => {
+                    info!(next_frame_expected, "Resending inputs the relay missed");
+                    let mut result = Ok(());
+                    for input in self
+                        .sent_inputs
+                        .iter()
+                        .filter(|input| input.frame >= next_frame_expected)
+                    {
+                        result = result.and(self.relay_connection.send(&Message::Input(*input)));
+                    }
+                    // the settings might have been lost along with the connection
+                    if self.playing_on_left_side && !self.configured {
+                        result = result.and(self.relay_connection.send(&Message::MatchConfig {
+                            config: self.config,
+                        }));
+                    }
+                    if let Err(e) = result {
+                        self.lose_connection(&e.to_string());
+                    }
+                }
+                _ => warn!(?message, "Unexpected message from server"),
+            }
+        }
+        if self.remote_input_violations >= MAX_REMOTE_INPUT_VIOLATIONS {
+            warn!(
+                violations = self.remote_input_violations,
+                "Ending the match, opponent keeps sending invalid inputs"
+            );
+            self.end_match(s, "Match ended, opponent sent invalid inputs");
+            return Vec::new();
+        }
+        if let Some(e) = error {
+            self.lose_connection(&e.to_string());
+        }
+        remote_inputs
+    }
+
+    /// The first frame again, for once the seed or the settings it depends on are known.
+    /// Only happens before anything has been simulated
+    fn restart_from_settings(&mut self) {
+        self.last_frames[0].game_after_inputs =
+            PongGameState::with_config(self.config, self.replay.seed);
+        self.replay.config = self.config;
+    }
+
+    /// Goes back to the title screen showing why the match is over
+    fn end_match(&mut self, s: &mut SceneAPI, reason: &str) {
+        self.replay.end_reason = String::from(reason);
+        s.new_scene = Some(Box::new(title_screen::TitleScreen::with_error(reason)));
+    }
+
+    /// Adds the frames whose inputs from both players have arrived since the last call to
+    /// the replay and confirms their events. They can't be rolled back anymore, so they're final.
+    /// Stops at the frame the match was won on, if there is one
+    fn record_confirmed_frames(&mut self) {
+        let confirmed_frames = self.cur_frame.min(self.next_remote_frame);
+        for frame in self.replay.frames()..confirmed_frames {
+            if self.final_state.is_some() {
+                break;
+            }
+            let index = (self.cur_frame - 1 - frame) as usize;
+            let confirmed = match self.last_frames.get(index) {
+                Some(confirmed) => confirmed,
+                None => break,
+            };
+            for (side, input) in confirmed.player_inputs.iter().enumerate() {
+                self.replay.inputs[side].push(*input);
+            }
+            self.events.confirmed(frame, &confirmed.events);
+            if confirmed.game_after_inputs.winner().is_some() {
+                self.final_state = Some(confirmed.game_after_inputs.clone());
             }
         }
+    }
+
+    /// Beeps each second of the opponent's reconnect or the server's shutdown countdown
+    fn tick_countdown(&mut self) {
+        let countdown_seconds = self
+            .opponent_reconnect_deadline
+            .or(self.server_shutdown_deadline)
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()).as_secs());
+        if countdown_seconds.is_some() && countdown_seconds != self.countdown_seconds {
+            self.audio.play(SoundEffect::Countdown);
+        }
+        self.countdown_seconds = countdown_seconds;
+    }
+
+    /// Starts trying to get back into the match, if the relay told us how
+    fn lose_connection(&mut self, reason: &str) {
+        if self.connection_lost {
+            return;
+        }
+        warn!(reason, "Lost connection to the server");
+        self.connection_lost = true;
+        if let (Some(relay_addr), Some(session_token)) = (self.relay_addr, self.session_token) {
+            self.reconnecting = Some(reconnect::start(
+                relay_addr,
+                ReconnectRequest {
+                    lobby_code: self.lobby_code,
+                    session_token,
+                    next_frame_expected: self.next_remote_frame,
+                },
+            ));
+        }
+    }
+
+    fn poll_reconnect(&mut self, s: &mut SceneAPI) {
+        let result = match self.reconnecting.as_ref().map(|rx| rx.try_recv()) {
+            Some(Ok(result)) => result,
+            _ => return,
+        };
+        self.reconnecting = None;
+        match result {
+            Ok(connection) => {
+                // the relay follows up with Resumed and the inputs we missed
+                self.relay_connection = NetThread::start(connection);
+                self.connection_lost = false;
+                self.relay_heartbeat = Heartbeat::new(Instant::now());
+                self.peer_heartbeat.heard_from(Instant::now());
+            }
+            Err(reason) => {
+                warn!(%reason, "Giving up on the match");
+                self.end_match(s, &reason);
+            }
+        }
+    }
+}
+
+impl PongGame {
+    /// Simulates one frame, or waits on the opponent. Returns whether a frame was simulated
+    fn tick(&mut self, _s: &mut SceneAPI, rl: &RaylibHandle) -> bool {
+        self.poll_reconnect(_s);
+
+        // too far ahead of the opponent to roll back to their next input when it arrives,
+        // so stop and wait for it. This is what freezes the game while they reconnect
+        let waiting_on_opponent =
+            self.cur_frame >= self.next_remote_frame + self.max_frames_ahead();
+
+        // fetch all input states available
+        let remote_inputs = self.receive_messages(_s);
+        self.send_heartbeats();
+        self.tick_countdown();
 
         let mut cur_frame_inputs: [Option<PongInputState>; 2] = [None, None];
 
@@ -436,7 +767,10 @@ impl Scene for PongGame {
             local_player_index = 1;
         }
 
-        if remote_inputs.len() == 0 {
+        // what's on screen now, for easing over whatever a rollback changes
+        let predicted = self.last_frames[0].game_after_inputs.clone();
+
+        if remote_inputs.is_empty() {
             // no remote input received, copy the last input for the appropriate remote player.
         } else {
             // rollback and input duplication logic
@@ -445,14 +779,14 @@ impl Scene for PongGame {
                     // input arrived from the future?? I think this means that I'm running behind, so
                     // TODO use this offset to figure out how much of the current frame to skip maybe
                     self.future_inputs.push(remote_input);
-                    rl.set_target_fps(61);
+                    self.timestep.rate = 61.0 / 60.0;
                     continue;
                 }
                 let frame_offset = self.cur_frame - remote_input.frame;
                 if frame_offset == 0 {
                     // remote input just happened
                     cur_frame_inputs[remote_player_index] = Some(remote_input);
-                    rl.set_target_fps(60);
+                    self.timestep.rate = 1.0;
                 } else if frame_offset > 0 {
                     // remote input happened frame_offset frames in the past
 
@@ -466,16 +800,16 @@ impl Scene for PongGame {
                     if frame_offset > 1 {
                         // TODO see how many frames of latency should be expected with occasional ping test
                         // or something then only slow down if there's more frames of latency than expected from the network
-                        rl.set_target_fps(59);
+                        self.timestep.rate = 59.0 / 60.0;
                     }
 
                     self.frames_rolled_back.add_data(frame_offset as f32);
 
                     // set the input of that frame to what was received
-                    let must_rollback = self.last_frames[cur_game_state_index as usize]
-                        .player_inputs[remote_player_index]
-                        .input
-                        != remote_input.input;
+                    let predicted_input = self.last_frames[cur_game_state_index as usize]
+                        .player_inputs[remote_player_index];
+                    let must_rollback = predicted_input.input != remote_input.input
+                        || predicted_input.buttons != remote_input.buttons;
 
                     if must_rollback {
                         self.last_frames[cur_game_state_index as usize].player_inputs
@@ -510,9 +844,15 @@ impl Scene for PongGame {
                         // resimulate the current frame with its updated inputs
                         let cur_frame: &mut PongInputAndGameState =
                             &mut self.last_frames[cur_game_state_index as usize];
-                        cur_frame
+                        let events = cur_frame
                             .game_after_inputs
                             .process_logic(&cur_frame.player_inputs);
+                        self.events.resimulated(
+                            self.cur_frame - 1 - cur_game_state_index as u32,
+                            &cur_frame.events,
+                            &events,
+                        );
+                        cur_frame.events = events;
 
                         cur_game_state_index -= 1;
                     }
@@ -520,6 +860,41 @@ impl Scene for PongGame {
             }
         }
 
+        self.smoothing
+            .corrected(&predicted, &self.last_frames[0].game_after_inputs);
+        self.record_confirmed_frames();
+
+        // only once both players' inputs agree on it, a predicted win could still be rolled back
+        if let Some(final_state) = &self.final_state {
+            let winner = final_state.winner().unwrap();
+            info!(winner, "Match won");
+            self.replay.end_reason = format!(
+                "{} won the match",
+                if winner == 0 { "host" } else { "joiner" }
+            );
+            _s.new_scene = Some(Box::new(results::Results::new(
+                final_state.clone(),
+                Some(local_player_index),
+            )));
+            return false;
+        }
+
+        if waiting_on_opponent || !self.seeded || !self.configured {
+            return false;
+        }
+
+        // construct local input from keys pressed
+        let local_input = PongInputState {
+            frame: self.cur_frame,
+            input: dimension_strength(rl, KeyboardKey::KEY_S, KeyboardKey::KEY_W),
+            buttons: if rl.is_key_down(KeyboardKey::KEY_SPACE) {
+                PongInputState::BUTTON_SERVE
+            } else {
+                0
+            },
+        };
+        self.send_input(local_input);
+
         if cur_frame_inputs[remote_player_index].is_none() {
             // check the future frame cache
             let mut successfully_used_future_frame_cache = false;
@@ -544,7 +919,8 @@ impl Scene for PongGame {
 
         let cur_frame_inputs = [cur_frame_inputs[0].unwrap(), cur_frame_inputs[1].unwrap()];
 
-        new_game_state.process_logic(&cur_frame_inputs);
+        let events = new_game_state.process_logic(&cur_frame_inputs);
+        self.events.simulated(self.cur_frame, &events);
 
         // println!("Sending my input...");
 
@@ -553,48 +929,249 @@ impl Scene for PongGame {
             PongInputAndGameState {
                 player_inputs: cur_frame_inputs,
                 game_after_inputs: new_game_state,
+                events,
             },
         );
 
-        if self.last_frames.len() > GAME_CONFIG.max_rollback_frames {
+        if self.last_frames.len() > self.config.max_rollback_frames {
             self.last_frames.pop();
         }
 
         self.cur_frame += 1;
+        self.smoothing.advance();
+
+        if self.cur_frame.is_multiple_of(SNAPSHOT_INTERVAL_FRAMES) && !self.connection_lost {
+            self.send_snapshot();
+        }
+        true
+    }
+}
+
+impl Scene for PongGame {
+    fn draw(&mut self, _s: &mut SceneAPI, d: &mut RaylibDrawHandle) {
+        d.clear_background(Color::WHITE);
+        // drawn part of the way from the frame before to the latest one, as far as the time
+        // now is between them. Held on the latest while the game waits for the opponent
+        let alpha = if self.simulating {
+            self.timestep.alpha()
+        } else {
+            1.0
+        };
+        let latest = &self.last_frames[0].game_after_inputs;
+        let previous = self
+            .last_frames
+            .get(1)
+            .map_or(latest, |f| &f.game_after_inputs);
+        self.smoothing
+            .smoothed(&self.smoothing.interpolated(previous, latest, alpha))
+            .draw(d);
+
+        // debug drawing
+        self.frames_rolled_back
+            .draw(d, Vector2::new(20.0, 20.0), Vector2::new(100.0, 70.0));
+        let latency_lines = [
+            describe_rtt("relay", self.relay_rtt()),
+            describe_rtt("peer", self.peer_rtt()),
+            format!("input delay {} frames", self.suggested_input_delay()),
+        ];
+        for (i, line) in latency_lines.iter().enumerate() {
+            d.draw_text(line, 20, 100 + 12 * i as i32, 10, Color::BLACK);
+        }
+        if self.remote_input_violations > 0 {
+            d.draw_text(
+                &format!(
+                    "invalid opponent inputs {}/{}",
+                    self.remote_input_violations, MAX_REMOTE_INPUT_VIOLATIONS
+                ),
+                20,
+                100 + 12 * latency_lines.len() as i32,
+                10,
+                Color::RED,
+            );
+        }
+
+        let latest = &self.last_frames[0].game_after_inputs;
+        let local_player_index = if self.playing_on_left_side { 0 } else { 1 };
+        let banner = if self.reconnecting.is_some() {
+            Some(String::from("CONNECTION LOST, RECONNECTING..."))
+        } else if self.connection_lost {
+            Some(String::from("CONNECTION LOST"))
+        } else if let Some(deadline) = self.opponent_reconnect_deadline {
+            Some(format!(
+                "OPPONENT DISCONNECTED, WAITING {}s FOR THEM",
+                deadline.saturating_duration_since(Instant::now()).as_secs()
+            ))
+        } else if self.opponent_not_responding() {
+            Some(String::from("OPPONENT NOT RESPONDING"))
+        } else if latest.server == local_player_index
+            && matches!(latest.serve_phase, ServePhase::Holding { .. })
+        {
+            Some(String::from("PRESS SPACE TO SERVE"))
+        } else {
+            self.server_shutdown_deadline.map(|deadline| {
+                format!(
+                    "SERVER SHUTTING DOWN IN {}s",
+                    deadline.saturating_duration_since(Instant::now()).as_secs()
+                )
+            })
+        };
+        if let Some(banner) = banner {
+            let font_size = 30;
+            d.draw_text(
+                &banner,
+                (self.config.arena_size.x as i32 - measure_text(&banner, font_size)) / 2,
+                self.config.arena_size.y as i32 - font_size - 20,
+                font_size,
+                Color::RED,
+            );
+        }
+    }
+
+    fn process(&mut self, _s: &mut SceneAPI, rl: &mut RaylibHandle) {
+        let _span = self.log_span.clone().entered();
+
+        fit_window(rl, &self.config);
+        for _ in 0..self.timestep.steps(rl.get_frame_time()) {
+            self.simulating = self.tick(_s, rl);
+            if _s.new_scene.is_some() {
+                return;
+            }
+        }
     }
     fn should_quit(&self) -> bool {
         false
     }
 }
 
+// whichever way the match ends, the scene is dropped
+impl Drop for PongGame {
+    fn drop(&mut self) {
+        if self.replay.frames() == 0 {
+            return;
+        }
+        self.replay.ended_at = unix_millis(SystemTime::now());
+        match self.replay.save_in(Path::new(REPLAY_DIR)) {
+            Ok(path) => info!(path = %path.display(), "Saved replay"),
+            Err(e) => warn!(error = %e, "Couldn't save the replay"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::net::{TcpListener, TcpStream};
+
+    fn game_on_loopback() -> PongGame {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        PongGame::new(
+            Connection::new(stream).unwrap(),
+            true,
+            1,
+            sim::GAME_CONFIG,
+            Audio::null(),
+        )
+    }
 
     #[test]
-    fn test_deterministic() {
-        let mut states: [PongGameState; 7] = [
-            PongGameState::new(),
-            PongGameState::new(),
-            PongGameState::new(),
-            PongGameState::new(),
-            PongGameState::new(),
-            PongGameState::new(),
-            PongGameState::new(),
-        ];
+    fn test_check_remote_input() {
+        let mut game = game_on_loopback();
+        assert_eq!(
+            game.check_remote_input(PongInputState::new(0, 0.5)),
+            Some(PongInputState::new(0, 0.5))
+        );
+        assert_eq!(game.remote_input_violations, 0);
 
-        for s in states.iter_mut() {
-            for frame in 0..5000 {
-                let elapsed_time = (frame as f32) * GAME_CONFIG.dt;
-                s.process_logic(&[
-                    PongInputState::from_input(elapsed_time.sin()),
-                    PongInputState::from_input(elapsed_time.cos()),
-                ]);
-            }
+        // bad values are fixed up so the frame still counts
+        assert_eq!(
+            game.check_remote_input(PongInputState::new(0, f32::NAN)),
+            Some(PongInputState::new(0, 0.0))
+        );
+        assert_eq!(
+            game.check_remote_input(PongInputState::new(0, 1e30)),
+            Some(PongInputState::new(0, 1.0))
+        );
+        assert_eq!(game.remote_input_violations, 2);
+
+        // frames that don't follow on are dropped
+        game.next_remote_frame = 1;
+        assert_eq!(game.check_remote_input(PongInputState::new(0, 0.0)), None);
+        assert_eq!(game.check_remote_input(PongInputState::new(7, 0.0)), None);
+        let too_far = game.config.max_frames_ahead_of_opponent() + 1;
+        game.next_remote_frame = too_far;
+        assert_eq!(
+            game.check_remote_input(PongInputState::new(too_far, 0.0)),
+            None
+        );
+        assert_eq!(game.remote_input_violations, 5);
+    }
+
+    #[test]
+    fn test_record_confirmed_frames() {
+        let mut game = game_on_loopback();
+        for frame in 0..3 {
+            game.last_frames.insert(
+                0,
+                PongInputAndGameState {
+                    player_inputs: [
+                        PongInputState::new(frame, frame as f32 / 2.0),
+                        PongInputState::new(frame, -1.0),
+                    ],
+                    game_after_inputs: PongGameState::new(),
+                    events: vec![SimEvent::Goal { scorer: 0 }],
+                },
+            );
         }
+        game.cur_frame = 3;
+
+        // the opponent's input for the last frame is still a guess
+        game.next_remote_frame = 2;
+        game.record_confirmed_frames();
+        assert_eq!(
+            game.replay.inputs,
+            [
+                vec![PongInputState::new(0, 0.0), PongInputState::new(1, 0.5)],
+                vec![PongInputState::new(0, -1.0), PongInputState::new(1, -1.0)]
+            ]
+        );
 
-        for s in states.iter().skip(1) {
-            assert_eq!(&states[0], s);
+        game.next_remote_frame = 3;
+        game.record_confirmed_frames();
+        assert_eq!(game.replay.frames(), 3);
+        assert_eq!(game.replay.inputs[0][2], PongInputState::new(2, 1.0));
+        assert!(game.final_state.is_none());
+
+        // don't leave a replay behind from the test
+        game.replay = Replay::default();
+    }
+
+    #[test]
+    fn test_stops_recording_when_won() {
+        let mut game = game_on_loopback();
+        for frame in 0..3 {
+            let mut state = PongGameState::new();
+            if frame >= 1 {
+                state.phase = MatchPhase::MatchOver { winner: 1 };
+            }
+            game.last_frames.insert(
+                0,
+                PongInputAndGameState {
+                    player_inputs: [
+                        PongInputState::new(frame, 0.0),
+                        PongInputState::new(frame, 0.0),
+                    ],
+                    game_after_inputs: state,
+                    events: Vec::new(),
+                },
+            );
         }
+        game.cur_frame = 3;
+        game.next_remote_frame = 3;
+        game.record_confirmed_frames();
+        assert_eq!(game.replay.frames(), 2);
+        assert_eq!(game.final_state.as_ref().and_then(|s| s.winner()), Some(1));
+
+        game.replay = Replay::default();
     }
 }
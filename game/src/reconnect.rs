@@ -0,0 +1,64 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use common::protocol::{self, Connection, ReconnectRequest};
+
+// the relay keeps the match around for its grace period, no point trying for much longer
+const GIVE_UP_AFTER: Duration = Duration::from_secs(60);
+const RETRY_EVERY: Duration = Duration::from_secs(1);
+
+/// Keeps trying to get back into the match on a background thread so the game
+/// doesn't freeze while it waits on the network. The receiver gets exactly one
+/// result, a connection that's back in the match or why it couldn't get back in
+pub fn start(
+    relay_addr: SocketAddr,
+    request: ReconnectRequest,
+) -> mpsc::Receiver<Result<Connection, String>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let started_at = Instant::now();
+        let mut attempt = 0;
+        let result = loop {
+            attempt += 1;
+            match try_reconnect(relay_addr, &request) {
+                Ok(Some(connection)) => break Ok(connection),
+                Ok(None) => break Err(String::from("The match is over")),
+                Err(e) => warn!(attempt, error = %e, "Reconnect attempt failed"),
+            }
+            if started_at.elapsed() >= GIVE_UP_AFTER {
+                break Err(String::from("Couldn't reconnect to the server"));
+            }
+            thread::sleep(RETRY_EVERY);
+        };
+        if result.is_ok() {
+            info!(attempt, "Reconnected to the match");
+        }
+        tx.send(result).unwrap_or_default();
+    });
+    rx
+}
+
+/// Ok(None) means the relay turned us away, retrying won't help
+fn try_reconnect(
+    relay_addr: SocketAddr,
+    request: &ReconnectRequest,
+) -> Result<Option<Connection>, std::io::Error> {
+    let mut stream = TcpStream::connect_timeout(&relay_addr, RETRY_EVERY)?;
+    stream.set_read_timeout(Some(RETRY_EVERY * 5))?;
+    stream.write_all(&request.encode())?;
+    let mut response = [0u8; 4];
+    stream.read_exact(&mut response)?;
+    stream.set_read_timeout(None)?;
+    match i32::from_le_bytes(response) {
+        protocol::RECONNECT_OK => Ok(Some(Connection::new(stream)?)),
+        code => {
+            warn!(code, "Relay rejected the reconnect");
+            Ok(None)
+        }
+    }
+}
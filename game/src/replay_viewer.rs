@@ -0,0 +1,257 @@
+use raylib::prelude::*;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tracing::info;
+
+use crate::imui::*;
+use crate::pong::{self, Draw, REPLAY_DIR};
+use crate::scene::*;
+use crate::title_screen;
+
+use common::replay::{self, Replay};
+use common::sim::PongGameState;
+
+// a copy of the game state is kept every this many frames so seeking only has to simulate
+// from the closest one before it instead of from the start
+const KEYFRAME_INTERVAL: u32 = 300;
+const SPEEDS: [f32; 5] = [0.25, 0.5, 1.0, 2.0, 4.0];
+const NORMAL_SPEED: usize = 2;
+
+/// The most recently saved replay, for when there's no path to one on the clipboard
+pub fn newest_replay() -> io::Result<PathBuf> {
+    let mut newest: Option<(std::time::SystemTime, PathBuf)> = None;
+    for entry in fs::read_dir(REPLAY_DIR)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(replay::FILE_EXTENSION) {
+            continue;
+        }
+        let modified = fs::metadata(&path)?.modified()?;
+        if newest
+            .as_ref()
+            .is_none_or(|(newest_modified, _)| modified > *newest_modified)
+        {
+            newest = Some((modified, path));
+        }
+    }
+    newest
+        .map(|(_, path)| path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no replays saved yet"))
+}
+
+/// Plays a recorded match back through the simulation, with pausing, stepping, slow and fast
+/// motion and seeking
+pub struct ReplayViewer {
+    replay: Replay,
+    // keyframes[i] is the state before frame i * KEYFRAME_INTERVAL was simulated
+    keyframes: Vec<PongGameState>,
+    game_state: PongGameState,
+    cur_frame: u32,
+    paused: bool,
+    speed_index: usize,
+    // how far into the next frame playback is, for speeds that aren't whole frames
+    frame_progress: f32,
+}
+
+impl ReplayViewer {
+    pub fn new(replay: Replay) -> Self {
+        // plays the whole match through once to build the keyframes
+        let mut keyframes = Vec::new();
+        let mut game_state = replay.initial_state();
+        for frame in 0..replay.frames() {
+            if frame.is_multiple_of(KEYFRAME_INTERVAL) {
+                keyframes.push(game_state.clone());
+            }
+            game_state.process_logic(&replay.inputs_for(frame));
+        }
+        if keyframes.is_empty() {
+            keyframes.push(game_state);
+        }
+
+        ReplayViewer {
+            game_state: keyframes[0].clone(),
+            replay,
+            keyframes,
+            cur_frame: 0,
+            paused: false,
+            speed_index: NORMAL_SPEED,
+            frame_progress: 0.0,
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let replay = Replay::load(path)?;
+        info!(
+            path = %path.display(),
+            lobby = replay.lobby_code,
+            frames = replay.frames(),
+            "Playing replay"
+        );
+        Ok(ReplayViewer::new(replay))
+    }
+
+    fn step(&mut self) {
+        if self.cur_frame < self.replay.frames() {
+            self.game_state
+                .process_logic(&self.replay.inputs_for(self.cur_frame));
+            self.cur_frame += 1;
+        }
+    }
+
+    /// Shows the state before `frame` is simulated, starting from the closest keyframe
+    fn seek(&mut self, frame: u32) {
+        let frame = frame.min(self.replay.frames());
+        let keyframe = ((frame / KEYFRAME_INTERVAL) as usize).min(self.keyframes.len() - 1);
+        self.game_state = self.keyframes[keyframe].clone();
+        self.cur_frame = keyframe as u32 * KEYFRAME_INTERVAL;
+        while self.cur_frame < frame {
+            self.step();
+        }
+        self.frame_progress = 0.0;
+    }
+
+    /// How many frames to play at the current speed now that `elapsed` seconds have passed
+    fn frames_to_play(&mut self, elapsed: f32) -> u32 {
+        if self.paused {
+            return 0;
+        }
+        self.frame_progress += SPEEDS[self.speed_index] * elapsed / self.replay.config.dt;
+        let frames = self.frame_progress.floor();
+        self.frame_progress -= frames;
+        frames as u32
+    }
+}
+
+impl Scene for ReplayViewer {
+    fn process(&mut self, s: &mut SceneAPI, rl: &mut RaylibHandle) {
+        if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE) {
+            s.new_scene = Some(Box::new(title_screen::TitleScreen::new()));
+            return;
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_SPACE) {
+            self.paused = !self.paused;
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_UP) {
+            self.speed_index = (self.speed_index + 1).min(SPEEDS.len() - 1);
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_DOWN) {
+            self.speed_index = self.speed_index.saturating_sub(1);
+        }
+        // stepping pauses so the frame stays on screen
+        if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) {
+            self.paused = true;
+            self.step();
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_LEFT) {
+            self.paused = true;
+            self.seek(self.cur_frame.saturating_sub(1));
+        }
+
+        pong::fit_window(rl, &self.replay.config);
+        for _ in 0..self.frames_to_play(rl.get_frame_time()) {
+            self.step();
+        }
+    }
+
+    fn draw(&mut self, _s: &mut SceneAPI, d: &mut RaylibDrawHandle) {
+        d.clear_background(Color::WHITE);
+        self.game_state.draw(d);
+
+        let frames = self.replay.frames();
+        let arena_size = self.replay.config.arena_size;
+        let bar_position = Vector2::new(20.0, arena_size.y - 30.0);
+        let bar_size = Vector2::new(arena_size.x - 40.0, 15.0);
+        let played = if frames == 0 {
+            1.0
+        } else {
+            self.cur_frame as f32 / frames as f32
+        };
+        if let Some(fraction) = scrub_bar(d, bar_position, bar_size, played) {
+            self.seek((fraction * frames as f32).round() as u32);
+        }
+
+        let mut status = format!(
+            "REPLAY {}/{} {}x{}",
+            self.cur_frame,
+            frames,
+            SPEEDS[self.speed_index],
+            if self.paused { " PAUSED" } else { "" }
+        );
+        if self.cur_frame == frames {
+            status = format!("{} - {}", status, self.replay.end_reason);
+        }
+        let help = "SPACE pause, LEFT/RIGHT step, UP/DOWN speed, BACKSPACE leave";
+        let font_size = 20;
+        for (i, line) in [status, String::from(help)].iter().enumerate() {
+            d.draw_text(
+                line,
+                (arena_size.x as i32 - measure_text(line, font_size)) / 2,
+                bar_position.y as i32 - (font_size + 5) * (2 - i as i32),
+                font_size,
+                Color::GRAY,
+            );
+        }
+    }
+
+    fn should_quit(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::PongInputState;
+
+    fn replay(frames: u32) -> Replay {
+        let inputs = |phase: f32| {
+            (0..frames)
+                .map(|frame| PongInputState::new(frame, (frame as f32 * 0.05 + phase).sin()))
+                .collect()
+        };
+        Replay {
+            seed: 5,
+            inputs: [inputs(0.0), inputs(1.0)],
+            ..Replay::default()
+        }
+    }
+
+    #[test]
+    fn test_seek_matches_playing_through() {
+        let replay = replay(2000);
+        let mut viewer = ReplayViewer::new(replay.clone());
+        assert_eq!(viewer.keyframes.len(), 7);
+
+        for target in [1234, 10, 2000, 0, 600] {
+            let mut expected = replay.initial_state();
+            for frame in 0..target {
+                expected.process_logic(&replay.inputs_for(frame));
+            }
+            viewer.seek(target);
+            assert_eq!(viewer.cur_frame, target);
+            assert_eq!(viewer.game_state, expected);
+        }
+    }
+
+    #[test]
+    fn test_frames_to_play() {
+        let mut viewer = ReplayViewer::new(replay(10));
+        let dt = viewer.replay.config.dt;
+        assert_eq!(viewer.frames_to_play(dt), 1);
+        // drawn twice as often, half the frames each time
+        assert_eq!(viewer.frames_to_play(dt / 2.0), 0);
+        assert_eq!(viewer.frames_to_play(dt / 2.0), 1);
+
+        viewer.speed_index = 0; // quarter speed
+        let played: Vec<u32> = (0..4).map(|_| viewer.frames_to_play(dt)).collect();
+        assert_eq!(played, vec![0, 0, 0, 1]);
+
+        viewer.speed_index = SPEEDS.len() - 1;
+        assert_eq!(viewer.frames_to_play(dt), 4);
+
+        viewer.paused = true;
+        assert_eq!(viewer.frames_to_play(dt), 0);
+    }
+}
@@ -0,0 +1,79 @@
+use raylib::prelude::*;
+
+use crate::imui::*;
+use crate::scene::*;
+use crate::title_screen;
+
+use common::sim::PongGameState;
+
+/// Shown once a match has been won, to both players and anyone watching
+pub struct Results {
+    final_state: PongGameState,
+    // None for spectators, who didn't win or lose
+    player_side: Option<usize>,
+}
+
+impl Results {
+    pub fn new(final_state: PongGameState, player_side: Option<usize>) -> Self {
+        Results {
+            final_state,
+            player_side,
+        }
+    }
+
+    fn headline(&self) -> String {
+        let winner = match self.final_state.winner() {
+            Some(winner) => winner,
+            None => return String::from("MATCH OVER"),
+        };
+        match self.player_side {
+            Some(side) if side == winner => String::from("YOU WIN"),
+            Some(_) => String::from("YOU LOSE"),
+            None if winner == 0 => String::from("LEFT WINS"),
+            None => String::from("RIGHT WINS"),
+        }
+    }
+}
+
+fn draw_centered(d: &mut RaylibDrawHandle, text: &str, y: i32, font_size: i32) {
+    d.draw_text(
+        text,
+        (d.get_screen_width() - measure_text(text, font_size)) / 2,
+        y,
+        font_size,
+        Color::WHITE,
+    );
+}
+
+impl Scene for Results {
+    fn process(&mut self, _s: &mut SceneAPI, _rl: &mut RaylibHandle) {}
+
+    fn draw(&mut self, s: &mut SceneAPI, d: &mut RaylibDrawHandle) {
+        d.clear_background(Color::GRAY);
+
+        draw_centered(d, &self.headline(), 150, 80);
+        let score = format!(
+            "{} - {}",
+            self.final_state.left_score.value, self.final_state.right_score.value
+        );
+        draw_centered(d, &score, 260, 50);
+        if self.final_state.config.rules.best_of > 1 {
+            let games = format!(
+                "games {} - {}",
+                self.final_state.games_won[0], self.final_state.games_won[1]
+            );
+            draw_centered(d, &games, 330, 30);
+        }
+
+        let screen_size = Vector2::new(d.get_screen_width() as f32, d.get_screen_height() as f32);
+        let button_size = Vector2::new(700.0, 60.0);
+        let position = Vector2::new((screen_size.x - button_size.x) / 2.0, 500.0);
+        if button(d, position, button_size, "BACK TO TITLE") {
+            s.new_scene = Some(Box::new(title_screen::TitleScreen::new()));
+        }
+    }
+
+    fn should_quit(&self) -> bool {
+        false
+    }
+}
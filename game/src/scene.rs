@@ -1,7 +1,14 @@
 use raylib::prelude::*;
 
+use common::sim::PongGameConfig;
+
+use crate::audio::Audio;
+
 pub struct SceneAPI {
     pub new_scene: Option<Box<dyn Scene>>,
+    pub audio: Audio,
+    // the settings loaded at startup, a match is played with the host's
+    pub config: PongGameConfig,
 }
 
 pub trait Scene {
@@ -0,0 +1,159 @@
+use tracing::trace;
+
+use common::sim::SimEvent;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum EventStatus {
+    /// Simulated with a guess at the opponent's input, a rollback could still take it back
+    Predicted,
+    /// Both players' inputs for the frame are in, it happened
+    Confirmed,
+    /// A rollback showed the predicted event didn't happen after all
+    Cancelled,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct FrameEvent {
+    pub frame: u32,
+    pub event: SimEvent,
+    pub status: EventStatus,
+}
+
+/// Something that wants to hear about what happens in the match, like sound or particles.
+/// Every event is heard as Predicted first, then once more as Confirmed or Cancelled
+pub trait EventSubscriber {
+    fn on_event(&mut self, event: &FrameEvent);
+}
+
+/// Turns the events from every time a frame is simulated, including resimulating it after a
+/// rollback, into one stream of predictions, confirmations and cancellations
+#[derive(Default)]
+pub struct EventStream {
+    subscribers: Vec<Box<dyn EventSubscriber>>,
+}
+
+impl EventStream {
+    pub fn new() -> Self {
+        EventStream::default()
+    }
+
+    pub fn subscribe(&mut self, subscriber: Box<dyn EventSubscriber>) {
+        self.subscribers.push(subscriber);
+    }
+
+    fn publish(&mut self, frame: u32, event: SimEvent, status: EventStatus) {
+        trace!(frame, ?event, ?status, "Simulation event");
+        let frame_event = FrameEvent {
+            frame,
+            event,
+            status,
+        };
+        for subscriber in self.subscribers.iter_mut() {
+            subscriber.on_event(&frame_event);
+        }
+    }
+
+    /// `frame` was simulated for the first time
+    pub fn simulated(&mut self, frame: u32, events: &[SimEvent]) {
+        for event in events.iter() {
+            self.publish(frame, *event, EventStatus::Predicted);
+        }
+    }
+
+    /// `frame` was simulated again after a rollback. Events it still has aren't repeated
+    pub fn resimulated(&mut self, frame: u32, before: &[SimEvent], after: &[SimEvent]) {
+        let mut unmatched: Vec<Option<&SimEvent>> = after.iter().map(Some).collect();
+        for event in before.iter() {
+            match unmatched.iter_mut().find(|e| **e == Some(event)) {
+                Some(matched) => *matched = None,
+                None => self.publish(frame, *event, EventStatus::Cancelled),
+            }
+        }
+        for event in unmatched.into_iter().flatten() {
+            self.publish(frame, *event, EventStatus::Predicted);
+        }
+    }
+
+    /// `frame` can't be rolled back anymore
+    pub fn confirmed(&mut self, frame: u32, events: &[SimEvent]) {
+        for event in events.iter() {
+            self.publish(frame, *event, EventStatus::Confirmed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Recorder(Rc<RefCell<Vec<FrameEvent>>>);
+
+    impl EventSubscriber for Recorder {
+        fn on_event(&mut self, event: &FrameEvent) {
+            self.0.borrow_mut().push(*event);
+        }
+    }
+
+    fn heard(events: &Rc<RefCell<Vec<FrameEvent>>>) -> Vec<(u32, SimEvent, EventStatus)> {
+        events
+            .borrow_mut()
+            .drain(..)
+            .map(|e| (e.frame, e.event, e.status))
+            .collect()
+    }
+
+    #[test]
+    fn test_rollback_events() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut stream = EventStream::new();
+        stream.subscribe(Box::new(Recorder(events.clone())));
+
+        let hit = SimEvent::PaddleHit {
+            side: 0,
+            speed: 450.0,
+        };
+        let goal = SimEvent::Goal { scorer: 1 };
+
+        stream.simulated(10, &[hit, SimEvent::WallBounce]);
+        assert_eq!(
+            heard(&events),
+            vec![
+                (10, hit, EventStatus::Predicted),
+                (10, SimEvent::WallBounce, EventStatus::Predicted)
+            ]
+        );
+
+        // the opponent didn't move the way we guessed, the paddle missed
+        stream.resimulated(
+            10,
+            &[hit, SimEvent::WallBounce],
+            &[SimEvent::WallBounce, goal],
+        );
+        assert_eq!(
+            heard(&events),
+            vec![
+                (10, hit, EventStatus::Cancelled),
+                (10, goal, EventStatus::Predicted)
+            ]
+        );
+
+        // nothing changed, nothing to say
+        stream.resimulated(
+            10,
+            &[SimEvent::WallBounce, goal],
+            &[SimEvent::WallBounce, goal],
+        );
+        assert!(heard(&events).is_empty());
+
+        stream.confirmed(10, &[SimEvent::WallBounce, goal]);
+        assert_eq!(
+            heard(&events),
+            vec![
+                (10, SimEvent::WallBounce, EventStatus::Confirmed),
+                (10, goal, EventStatus::Confirmed)
+            ]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use common::sim::{PongGameState, Vector2};
+
+/// Hides rollback corrections by drawing the paddles and ball where they were predicted to be
+/// and easing them over to where they really are. Only ever changes what's drawn, the
+/// simulation itself is left alone
+pub struct Smoothing {
+    /// How much of what's left of a correction is made up each frame, 1 snaps straight away
+    pub blend: f32,
+    /// Corrections further than this snap instead, easing a ball back from behind the goal
+    /// after a serve would look worse than the jump
+    pub snap_distance: f32,
+    // how far from the simulation each one is drawn: left paddle, right paddle, ball
+    offsets: [Vector2; 3],
+}
+
+fn positions(state: &PongGameState) -> [Vector2; 3] {
+    [
+        state.left_paddle.position,
+        state.right_paddle.position,
+        state.ball.position,
+    ]
+}
+
+impl Smoothing {
+    pub fn new(blend: f32, snap_distance: f32) -> Smoothing {
+        Smoothing {
+            blend,
+            snap_distance,
+            offsets: [Vector2::default(); 3],
+        }
+    }
+
+    /// A rollback changed the latest state from `predicted` to `corrected`
+    pub fn corrected(&mut self, predicted: &PongGameState, corrected: &PongGameState) {
+        let predicted = positions(predicted);
+        let corrected = positions(corrected);
+        for (i, offset) in self.offsets.iter_mut().enumerate() {
+            *offset += predicted[i] - corrected[i];
+            if offset.length() > self.snap_distance {
+                *offset = Vector2::default();
+            }
+        }
+    }
+
+    /// Eases everything a frame closer to the simulation
+    pub fn advance(&mut self) {
+        for offset in self.offsets.iter_mut() {
+            *offset = *offset * (1.0 - self.blend);
+            // close enough that it wouldn't move a pixel
+            if offset.length() < 0.1 {
+                *offset = Vector2::default();
+            }
+        }
+    }
+
+    /// `current` with everything `alpha` of the way there from where it was in `previous`.
+    /// Anything that jumped further than the snap distance, like the ball on a serve, is left
+    /// where it is
+    pub fn interpolated(
+        &self,
+        previous: &PongGameState,
+        current: &PongGameState,
+        alpha: f32,
+    ) -> PongGameState {
+        let from = positions(previous);
+        let to = positions(current);
+        let between: Vec<Vector2> = from
+            .iter()
+            .zip(to.iter())
+            .map(|(from, to)| {
+                if (*to - *from).length() > self.snap_distance {
+                    *to
+                } else {
+                    *from + (*to - *from) * alpha
+                }
+            })
+            .collect();
+        let mut interpolated = current.clone();
+        interpolated.left_paddle.position = between[0];
+        interpolated.right_paddle.position = between[1];
+        interpolated.ball.position = between[2];
+        interpolated
+    }
+
+    /// A copy of `state` with everything where it should be drawn
+    pub fn smoothed(&self, state: &PongGameState) -> PongGameState {
+        let mut smoothed = state.clone();
+        smoothed.left_paddle.position += self.offsets[0];
+        smoothed.right_paddle.position += self.offsets[1];
+        smoothed.ball.position += self.offsets[2];
+        smoothed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_right_paddle_at(y: f32) -> PongGameState {
+        let mut state = PongGameState::new();
+        state.right_paddle.position.y = y;
+        state
+    }
+
+    #[test]
+    fn test_eases_into_corrections() {
+        let mut smoothing = Smoothing::new(0.5, 100.0);
+        let corrected = with_right_paddle_at(40.0);
+        smoothing.corrected(&with_right_paddle_at(80.0), &corrected);
+
+        // still drawn where it was predicted, then halfway there each frame
+        let mut drawn_at = Vec::new();
+        for _ in 0..3 {
+            drawn_at.push(smoothing.smoothed(&corrected).right_paddle.position.y);
+            smoothing.advance();
+        }
+        assert_eq!(drawn_at, vec![80.0, 60.0, 50.0]);
+        assert_eq!(smoothing.smoothed(&corrected).left_paddle.position.y, 0.0);
+
+        for _ in 0..10 {
+            smoothing.advance();
+        }
+        assert_eq!(smoothing.smoothed(&corrected), corrected);
+    }
+
+    #[test]
+    fn test_interpolated() {
+        let smoothing = Smoothing::new(0.5, 100.0);
+        let previous = with_right_paddle_at(40.0);
+        let mut current = with_right_paddle_at(80.0);
+        current.ball.position.x = previous.ball.position.x + 500.0;
+
+        let between = smoothing.interpolated(&previous, &current, 0.25);
+        assert_eq!(between.right_paddle.position.y, 50.0);
+        // the ball jumped too far to have been anywhere in between
+        assert_eq!(between.ball.position, current.ball.position);
+        assert_eq!(smoothing.interpolated(&previous, &current, 1.0), current);
+    }
+
+    #[test]
+    fn test_snaps_past_threshold() {
+        let mut smoothing = Smoothing::new(0.5, 100.0);
+        let corrected = with_right_paddle_at(300.0);
+        smoothing.corrected(&with_right_paddle_at(80.0), &corrected);
+        assert_eq!(smoothing.smoothed(&corrected), corrected);
+
+        // corrections that add up past it snap too
+        smoothing.corrected(&with_right_paddle_at(360.0), &corrected);
+        smoothing.corrected(&with_right_paddle_at(360.0), &corrected);
+        assert_eq!(smoothing.smoothed(&corrected), corrected);
+    }
+}
@@ -0,0 +1,225 @@
+use raylib::prelude::*;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use tracing::{info, info_span, warn};
+
+use crate::pong::{self, Draw};
+use crate::results;
+use crate::scene::*;
+use crate::timestep::FixedTimestep;
+use crate::title_screen;
+
+use common::protocol::{Connection, Message};
+use common::sim::{PongGameConfig, PongGameState, GAME_CONFIG};
+use common::PongInputState;
+
+// how many frames behind the newest input from both players the spectator plays, so an
+// input arriving a little late doesn't stall the match
+const DELAY_FRAMES: u32 = 6;
+
+/// How many frames to simulate this process call given how many have both players'
+/// inputs ready and how many are due by the clock. Waits for the delay to build up before
+/// starting, and skips ahead if it falls too far behind
+fn frames_to_simulate(frames_ready: u32, frames_due: u32, started: &mut bool) -> u32 {
+    if frames_ready == 0 {
+        // ran dry, build the delay back up before playing again
+        *started = false;
+        return 0;
+    }
+    if !*started {
+        if frames_ready < DELAY_FRAMES {
+            return 0;
+        }
+        *started = true;
+    }
+    if frames_ready > DELAY_FRAMES * 2 {
+        frames_ready - DELAY_FRAMES
+    } else {
+        frames_due.min(frames_ready)
+    }
+}
+
+/// Watches a running match by playing both players' inputs through the simulation.
+/// Inputs arrive already confirmed, so there's nothing to predict or roll back
+pub struct Spectator {
+    connection: Connection,
+    game_state: PongGameState,
+    // the match's seed and the host's settings, the relay sends both before any inputs
+    seed: u64,
+    config: PongGameConfig,
+    inputs: [VecDeque<PongInputState>; 2], // 0 is left, 1 is right
+    cur_frame: u32,
+    started: bool,
+    timestep: FixedTimestep,
+    server_shutdown_deadline: Option<Instant>,
+    log_span: tracing::Span,
+}
+
+impl Spectator {
+    pub fn new(connection: Connection, lobby_code: i32) -> Self {
+        Spectator {
+            connection,
+            game_state: PongGameState::new(),
+            seed: 0,
+            config: GAME_CONFIG,
+            inputs: [VecDeque::new(), VecDeque::new()],
+            cur_frame: 0,
+            started: false,
+            timestep: FixedTimestep::new(GAME_CONFIG.dt),
+            server_shutdown_deadline: None,
+            log_span: info_span!("spectate", lobby = lobby_code),
+        }
+    }
+
+    fn frames_ready(&self) -> u32 {
+        self.inputs
+            .iter()
+            .map(|inputs| match inputs.back() {
+                Some(newest) => (newest.frame + 1).saturating_sub(self.cur_frame),
+                None => 0,
+            })
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+impl Scene for Spectator {
+    fn process(&mut self, s: &mut SceneAPI, rl: &mut RaylibHandle) {
+        let _span = self.log_span.clone().entered();
+
+        let (messages, error) = self.connection.receive();
+        for message in messages {
+            match message {
+                Message::PlayerInput { side, input } if (side as usize) < self.inputs.len() => {
+                    self.inputs[side as usize].push_back(input);
+                }
+                Message::MatchSeed { seed } if self.cur_frame == 0 => {
+                    self.seed = seed;
+                    self.game_state = PongGameState::with_config(self.config, seed);
+                }
+                Message::MatchConfig { config } if self.cur_frame == 0 => {
+                    if let Err(e) = config.validate() {
+                        warn!(error = %e, "Host's game settings are unusable");
+                        s.new_scene = Some(Box::new(title_screen::TitleScreen::with_error(
+                            &format!("Host's game settings are invalid: {}", e),
+                        )));
+                        return;
+                    }
+                    self.config = config;
+                    self.timestep = FixedTimestep::new(config.dt);
+                    self.game_state = PongGameState::with_config(config, self.seed);
+                }
+                Message::Snapshot { frame, state } if self.cur_frame == 0 => {
+                    // joined late, start from where the match is instead of from the beginning
+                    info!(frame, "Starting from a snapshot");
+                    self.game_state = PongGameState::from_bytes(&state, self.config);
+                    self.cur_frame = frame + 1;
+                }
+                Message::MatchResult {
+                    left_score,
+                    right_score,
+                    ..
+                } => {
+                    info!(left_score, right_score, "Match over");
+                    s.new_scene = Some(Box::new(title_screen::TitleScreen::with_error(&format!(
+                        "Match over, official score {} - {}",
+                        left_score, right_score
+                    ))));
+                    return;
+                }
+                Message::ServerShuttingDown { seconds_left } => {
+                    info!(seconds_left, "Relay is shutting down");
+                    self.server_shutdown_deadline =
+                        Some(Instant::now() + Duration::from_secs(seconds_left as u64));
+                }
+                _ => warn!(?message, "Unexpected message from server"),
+            }
+        }
+        if let Some(e) = error {
+            info!(error = %e, "Stopped spectating");
+            s.new_scene = Some(Box::new(title_screen::TitleScreen::with_error(
+                "The match is over",
+            )));
+            return;
+        }
+
+        pong::fit_window(rl, &self.config);
+        let frames_due = self.timestep.steps(rl.get_frame_time());
+        for _ in 0..frames_to_simulate(self.frames_ready(), frames_due, &mut self.started) {
+            let mut frame_inputs = [PongInputState::new(0, 0.0); 2];
+            for (side, inputs) in self.inputs.iter_mut().enumerate() {
+                // anything older than the current frame was already played
+                while inputs.front().map(|input| input.frame) < Some(self.cur_frame) {
+                    inputs.pop_front();
+                }
+                frame_inputs[side] = inputs.pop_front().unwrap();
+            }
+            self.game_state.process_logic(&frame_inputs);
+            self.cur_frame += 1;
+            if let Some(winner) = self.game_state.winner() {
+                info!(winner, "Match won");
+                s.new_scene = Some(Box::new(results::Results::new(
+                    self.game_state.clone(),
+                    None,
+                )));
+                return;
+            }
+        }
+    }
+
+    fn draw(&mut self, _s: &mut SceneAPI, d: &mut RaylibDrawHandle) {
+        d.clear_background(Color::WHITE);
+        self.game_state.draw(d);
+
+        let status = match self.server_shutdown_deadline {
+            Some(deadline) => format!(
+                "SPECTATING - SERVER SHUTTING DOWN IN {}s",
+                deadline.saturating_duration_since(Instant::now()).as_secs()
+            ),
+            None if !self.started => String::from("SPECTATING - BUFFERING..."),
+            None => String::from("SPECTATING"),
+        };
+        let font_size = 30;
+        d.draw_text(
+            &status,
+            (self.config.arena_size.x as i32 - measure_text(&status, font_size)) / 2,
+            self.config.arena_size.y as i32 - font_size - 20,
+            font_size,
+            Color::GRAY,
+        );
+    }
+
+    fn should_quit(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_to_simulate() {
+        let mut started = false;
+        assert_eq!(frames_to_simulate(DELAY_FRAMES - 1, 1, &mut started), 0);
+        assert!(!started);
+        assert_eq!(frames_to_simulate(DELAY_FRAMES, 1, &mut started), 1);
+        assert!(started);
+        // once going, plays frames as they're due even with a small buffer
+        assert_eq!(frames_to_simulate(1, 1, &mut started), 1);
+        assert_eq!(frames_to_simulate(DELAY_FRAMES, 0, &mut started), 0);
+        assert_eq!(frames_to_simulate(DELAY_FRAMES, 2, &mut started), 2);
+        assert_eq!(frames_to_simulate(1, 2, &mut started), 1);
+        // catches up when far behind
+        assert_eq!(
+            frames_to_simulate(DELAY_FRAMES * 3, 1, &mut started),
+            DELAY_FRAMES * 2
+        );
+        // waits for the delay again after running dry
+        assert_eq!(frames_to_simulate(0, 1, &mut started), 0);
+        assert!(!started);
+        assert_eq!(frames_to_simulate(1, 1, &mut started), 0);
+    }
+}
@@ -0,0 +1,79 @@
+// most frames simulated for one rendered frame. After a long stall the game carries on from
+// where it was instead of racing through everything it missed
+const MAX_STEPS_PER_UPDATE: u32 = 8;
+
+/// Works out how many simulation frames of `dt` to run for however long the last
+/// rendered frame took, so the game runs at the same speed whatever the display's refresh rate
+pub struct FixedTimestep {
+    /// How fast simulated time passes compared to real time, nudged a little either side of 1
+    /// to drift back in step with the opponent
+    pub rate: f32,
+    // simulated time owed that doesn't add up to a whole frame yet
+    accumulator: f32,
+    // seconds per frame, the match's config.dt
+    dt: f32,
+}
+
+impl FixedTimestep {
+    pub fn new(dt: f32) -> Self {
+        FixedTimestep {
+            rate: 1.0,
+            accumulator: 0.0,
+            dt,
+        }
+    }
+
+    /// How many frames to simulate now that `elapsed` seconds have passed
+    pub fn steps(&mut self, elapsed: f32) -> u32 {
+        self.accumulator += elapsed.max(0.0) * self.rate;
+        let steps = (self.accumulator / self.dt).floor() as u32;
+        if steps > MAX_STEPS_PER_UPDATE {
+            self.accumulator = 0.0;
+            return MAX_STEPS_PER_UPDATE;
+        }
+        self.accumulator -= steps as f32 * self.dt;
+        steps
+    }
+
+    /// How far the time now is between the last simulated frame and the next one, from 0 to 1,
+    /// for drawing in between them
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.dt).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::sim::GAME_CONFIG;
+
+    #[test]
+    fn test_steps_independent_of_render_rate() {
+        // a second at 144hz, 60hz and 30hz is a second of simulation
+        for render_rate in [144.0, 60.0, 30.0] {
+            let mut timestep = FixedTimestep::new(GAME_CONFIG.dt);
+            let steps: u32 = (0..render_rate as u32)
+                .map(|_| timestep.steps(1.0 / render_rate))
+                .sum();
+            assert!((59..=60).contains(&steps), "{} at {}hz", steps, render_rate);
+        }
+
+        let mut timestep = FixedTimestep::new(GAME_CONFIG.dt);
+        assert_eq!(timestep.steps(GAME_CONFIG.dt * 0.25), 0);
+        assert!((timestep.alpha() - 0.25).abs() < 0.001);
+        assert_eq!(timestep.steps(GAME_CONFIG.dt), 1);
+        assert!((timestep.alpha() - 0.25).abs() < 0.001);
+
+        // a long stall doesn't make it race to catch up
+        assert_eq!(timestep.steps(2.0), MAX_STEPS_PER_UPDATE);
+        assert_eq!(timestep.alpha(), 0.0);
+
+        timestep.rate = 2.0;
+        assert_eq!(timestep.steps(GAME_CONFIG.dt), 2);
+
+        // a match played at 120hz
+        let mut timestep = FixedTimestep::new(1.0 / 120.0);
+        let steps: u32 = (0..60).map(|_| timestep.steps(1.0 / 60.0)).sum();
+        assert!((119..=120).contains(&steps), "{} at 120hz", steps);
+    }
+}
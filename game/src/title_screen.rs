@@ -2,17 +2,23 @@ use raylib::prelude::*;
 
 use std::io::{Read, Write};
 use std::net::TcpStream;
+use std::path::PathBuf;
+
+use tracing::{debug, error, info, info_span, warn};
 
 use crate::awaiting_opponent;
 use crate::imui::*;
 use crate::pong;
+use crate::replay_viewer;
 use crate::scene::*;
+use crate::spectator;
 
+use common::protocol::{self, Connection};
 use common::{DEVEL_IP, PROD_IP};
 
 pub struct TitleScreen {
     should_quit: bool,
-    failed_to_connect_to_lobby: bool,
+    error_message: Option<String>,
     production_url: bool,
 }
 
@@ -20,10 +26,17 @@ impl TitleScreen {
     pub fn new() -> Self {
         TitleScreen {
             should_quit: false,
-            failed_to_connect_to_lobby: false,
+            error_message: None,
             production_url: false,
         }
     }
+
+    // for returning to the title screen when something went wrong in another scene
+    pub fn with_error(error_message: &str) -> Self {
+        let mut to_return = TitleScreen::new();
+        to_return.error_message = Some(String::from(error_message));
+        to_return
+    }
 }
 
 impl Scene for TitleScreen {
@@ -45,12 +58,11 @@ impl Scene for TitleScreen {
 
         let screen_size = Vector2::new(d.get_screen_width() as f32, d.get_screen_height() as f32);
 
-        if self.failed_to_connect_to_lobby {
-            let err = "Failed to connect to lobby server";
+        if let Some(err) = &self.error_message {
             d.draw_text(
                 err,
                 (screen_size.x / 2.0
-                    - measure_text_ex(d.get_font_default(), err, 30.0, 1.0).x / 2.0)
+                    - measure_text_ex(d.get_font_default(), err.as_str(), 30.0, 1.0).x / 2.0)
                     as i32,
                 30,
                 30,
@@ -58,7 +70,7 @@ impl Scene for TitleScreen {
             );
         }
 
-        let num_buttons = 3;
+        let num_buttons = 5;
         let button_size = Vector2::new(700.0, 60.0);
         let set_of_buttons_size =
             button_size + Vector2::new(0.0, button_size.y * ((num_buttons - 1) as f32));
@@ -66,38 +78,42 @@ impl Scene for TitleScreen {
         let mut cur_place_pos = screen_size / 2.0 - set_of_buttons_size / 2.0;
 
         if button(d, cur_place_pos, button_size, "HOST") {
-            println!("Connecting to {}", ip_to_connect_to);
+            let _span = info_span!("host", relay = ip_to_connect_to).entered();
+            debug!("Connecting to relay");
             match TcpStream::connect(ip_to_connect_to) {
                 Ok(mut stream) => {
-                    println!("Successfully connected to server in port 3333");
+                    debug!("Connected to relay");
 
-                    let msg: [u8; 5] = [1, 0, 0, 0, 0];
+                    let msg: [u8; 5] = [protocol::CREATE_LOBBY_COMMAND, 0, 0, 0, 0];
 
                     stream.write(&msg).unwrap();
-                    println!("Sent create lobby command, awaiting lobby code...");
+                    debug!("Sent create lobby command, awaiting lobby code...");
 
                     let mut data = [0 as u8; 4]; // using 4 byte buffer
                     match stream.read_exact(&mut data) {
                         Ok(_) => {
                             let response: i32 = i32::from_le_bytes(data);
 
-                            if response != 0 {
-                                println!("New lobby created! Lobby code: {}", response);
+                            if response != protocol::LOBBY_CREATE_FAILED {
+                                info!(lobby = response, "New lobby created");
                                 _s.new_scene = Some(Box::new(
                                     awaiting_opponent::AwaitingOpponent::new(stream, response),
                                 ));
                             } else {
-                                println!("Error creating lobby");
+                                error!("Error creating lobby");
+                                self.error_message = Some(String::from(
+                                    "Server refused to create a lobby, it may be shutting down",
+                                ));
                             }
                         }
                         Err(e) => {
-                            println!("Failed to receive data: {}", e);
+                            error!(error = %e, "Failed to receive data");
                         }
                     }
                 }
                 Err(e) => {
-                    println!("Failed to connect: {}", e);
-                    self.failed_to_connect_to_lobby = true;
+                    error!(error = %e, "Failed to connect to relay");
+                    self.error_message = Some(String::from("Failed to connect to lobby server"));
                 }
             }
         }
@@ -107,13 +123,13 @@ impl Scene for TitleScreen {
             let lobby_code_string = d.get_clipboard_text().unwrap(); // TODO handle error where clipboard content is not a string, a utf8 error instead
             let lobby_code = lobby_code_string.parse::<i32>().unwrap(); // TODO handle error where clipboard content is not a proper lobby code
 
+            let _span = info_span!("join", relay = ip_to_connect_to, lobby = lobby_code).entered();
             match TcpStream::connect(ip_to_connect_to) {
                 Ok(mut stream) => {
-                    println!("Successfully connected to server in port 3333");
-                    println!("Requesting to join lobby {}", lobby_code);
+                    debug!("Connected to relay, requesting to join lobby");
                     let lobby_code_bytes = lobby_code.to_le_bytes();
                     let msg: [u8; 5] = [
-                        2,
+                        protocol::JOIN_LOBBY_COMMAND,
                         lobby_code_bytes[0],
                         lobby_code_bytes[1],
                         lobby_code_bytes[2],
@@ -121,7 +137,7 @@ impl Scene for TitleScreen {
                     ];
 
                     stream.write(&msg).unwrap();
-                    println!("Sent join lobby command, awaiting lobby code...");
+                    debug!("Sent join lobby command, awaiting response...");
 
                     // TODO refactor this into a function based off of the host code
                     let mut data = [0 as u8; 4]; // using 4 byte buffer
@@ -129,24 +145,105 @@ impl Scene for TitleScreen {
                         Ok(_) => {
                             let response: i32 = i32::from_le_bytes(data);
 
-                            if response == 200 {
-                                println!("Joined Lobby!");
-                                _s.new_scene = Some(Box::new(pong::PongGame::new(stream, false)));
+                            if response == protocol::JOIN_OK {
+                                info!("Joined lobby");
+                                _s.new_scene = Some(Box::new(pong::PongGame::new(
+                                    Connection::new(stream).unwrap(),
+                                    false,
+                                    lobby_code,
+                                    _s.config,
+                                    _s.audio.clone(),
+                                )));
                             } else {
-                                println!(
-                                    "Error creating lobby, response from server: {}",
-                                    response
-                                );
+                                warn!(response, "Failed to join lobby");
+                                self.error_message = Some(String::from(
+                                    if response == protocol::JOIN_SERVER_SHUTTING_DOWN {
+                                        "Server is shutting down, try again later"
+                                    } else {
+                                        "No lobby with that code"
+                                    },
+                                ));
+                            }
+                        }
+                        Err(e) => {
+                            error!(error = %e, "Failed to receive data");
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!(error = %e, "Failed to connect to relay");
+                    self.error_message = Some(String::from("Failed to connect to lobby server"));
+                }
+            }
+        }
+        cur_place_pos.y += button_size.y + spacing;
+
+        if button(d, cur_place_pos, button_size, "SPECTATE FROM CLIPBOARD") {
+            let lobby_code_string = d.get_clipboard_text().unwrap_or_default();
+            match lobby_code_string.trim().parse::<i32>() {
+                Ok(lobby_code) => {
+                    let _span =
+                        info_span!("spectate", relay = ip_to_connect_to, lobby = lobby_code)
+                            .entered();
+                    match TcpStream::connect(ip_to_connect_to) {
+                        Ok(mut stream) => {
+                            let mut msg = [protocol::SPECTATE_COMMAND, 0, 0, 0, 0];
+                            msg[1..].copy_from_slice(&lobby_code.to_le_bytes());
+                            let mut data = [0u8; 4];
+                            match stream
+                                .write_all(&msg)
+                                .and_then(|_| stream.read_exact(&mut data))
+                            {
+                                Ok(_) => match i32::from_le_bytes(data) {
+                                    protocol::SPECTATE_OK => {
+                                        info!("Spectating match");
+                                        _s.new_scene = Some(Box::new(spectator::Spectator::new(
+                                            Connection::new(stream).unwrap(),
+                                            lobby_code,
+                                        )));
+                                    }
+                                    protocol::JOIN_SERVER_SHUTTING_DOWN => {
+                                        self.error_message = Some(String::from(
+                                            "Server is shutting down, try again later",
+                                        ));
+                                    }
+                                    response => {
+                                        warn!(response, "Failed to spectate match");
+                                        self.error_message =
+                                            Some(String::from("No match running with that code"));
+                                    }
+                                },
+                                Err(e) => {
+                                    error!(error = %e, "Failed to request to spectate");
+                                }
                             }
                         }
                         Err(e) => {
-                            println!("Failed to receive data: {}", e);
+                            error!(error = %e, "Failed to connect to relay");
+                            self.error_message =
+                                Some(String::from("Failed to connect to lobby server"));
                         }
                     }
                 }
+                Err(_) => {
+                    self.error_message = Some(String::from("Clipboard doesn't hold a lobby code"));
+                }
+            }
+        }
+        cur_place_pos.y += button_size.y + spacing;
+
+        if button(d, cur_place_pos, button_size, "WATCH REPLAY FROM CLIPBOARD") {
+            // a path to a replay file, or nothing for the most recent match
+            let clipboard = d.get_clipboard_text().unwrap_or_default();
+            let path = match clipboard.trim() {
+                "" => replay_viewer::newest_replay(),
+                path => Ok(PathBuf::from(path)),
+            };
+            match path.and_then(|path| replay_viewer::ReplayViewer::load(&path)) {
+                Ok(viewer) => _s.new_scene = Some(Box::new(viewer)),
                 Err(e) => {
-                    println!("Failed to connect: {}", e);
-                    self.failed_to_connect_to_lobby = true;
+                    warn!(error = %e, "Failed to load replay");
+                    self.error_message = Some(format!("Couldn't load replay: {}", e));
                 }
             }
         }
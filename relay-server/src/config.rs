@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const USAGE: &str = "usage: relay-server [--log-json] [--shutdown-timeout SECONDS] \
+                         [--reconnect-grace SECONDS] [--authoritative] [--record-dir DIR]";
+
+pub struct RelayConfig {
+    pub log_json: bool,
+    // how long running matches get to finish after SIGINT/SIGTERM before they're cut off
+    pub shutdown_timeout: Duration,
+    // how long a match waits for a player whose connection dropped to come back
+    pub reconnect_grace: Duration,
+    // run the simulation for every match too, rejecting impossible inputs and keeping the
+    // official score
+    pub authoritative: bool,
+    // where every match gets saved as a replay when it ends, nothing is recorded without it
+    pub record_dir: Option<PathBuf>,
+}
+
+fn parse_seconds(flag: &str, value: Option<String>) -> Result<Duration, String> {
+    let value = value.ok_or(format!("{} needs a number of seconds", flag))?;
+    let seconds = value
+        .parse::<u64>()
+        .map_err(|e| format!("bad {} '{}': {}", flag, value, e))?;
+    Ok(Duration::from_secs(seconds))
+}
+
+impl RelayConfig {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<RelayConfig, String> {
+        let mut config = RelayConfig {
+            log_json: false,
+            shutdown_timeout: Duration::from_secs(60),
+            reconnect_grace: Duration::from_secs(30),
+            authoritative: false,
+            record_dir: None,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--log-json" => config.log_json = true,
+                "--authoritative" => config.authoritative = true,
+                "--shutdown-timeout" => {
+                    config.shutdown_timeout = parse_seconds(&arg, args.next())?;
+                }
+                "--reconnect-grace" => {
+                    config.reconnect_grace = parse_seconds(&arg, args.next())?;
+                }
+                "--record-dir" => {
+                    let dir = args.next().ok_or("--record-dir needs a directory")?;
+                    config.record_dir = Some(PathBuf::from(dir));
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<RelayConfig, String> {
+        RelayConfig::from_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let config = parse(&["--shutdown-timeout", "5", "--log-json"]).unwrap();
+        assert!(config.log_json);
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(5));
+        assert_eq!(config.reconnect_grace, Duration::from_secs(30));
+        assert!(!config.authoritative);
+        assert_eq!(config.record_dir, None);
+
+        let config = parse(&["--reconnect-grace", "0", "--authoritative"]).unwrap();
+        assert_eq!(config.reconnect_grace, Duration::from_secs(0));
+        assert!(config.authoritative);
+
+        let config = parse(&["--record-dir", "/var/lib/pong"]).unwrap();
+        assert_eq!(config.record_dir, Some(PathBuf::from("/var/lib/pong")));
+        assert!(parse(&["--record-dir"]).is_err());
+
+        assert!(parse(&["--shutdown-timeout"]).is_err());
+        assert!(parse(&["--shutdown-timeout", "soon"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+    }
+}
@@ -0,0 +1,25 @@
+use tracing_subscriber::EnvFilter;
+
+// used when RUST_LOG isn't set, e.g. RUST_LOG=relay_server=debug for more detail
+const DEFAULT_FILTER: &str = "info";
+
+/// Sets up the global logger. The level and per module filtering come from RUST_LOG,
+/// json output is one object per line with the connection/lobby/match spans attached
+/// so it can be shipped straight to a log aggregator
+pub fn init(json: bool) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_thread_names(true);
+
+    if json {
+        builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init();
+    } else {
+        builder.init();
+    }
+}
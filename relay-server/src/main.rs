@@ -1,116 +1,199 @@
 use rand;
 use std::collections::HashMap;
+use std::env;
+use std::fs;
 use std::io;
 use std::io::{Read, Write};
-use std::mem::size_of;
-use std::net::{Shutdown, TcpListener, TcpStream};
+use std::net::{Shutdown as NetShutdown, TcpListener, TcpStream};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time;
 
-use common::PongInputState;
+use tracing::{debug, error, info, info_span, warn};
+
+use common::protocol::{self, Connection, Message, ReconnectRequest};
 use common::PORT;
 
-fn funnel_packets(
-    source_stream: &mut TcpStream,
-    target_stream: &mut TcpStream,
-) -> Result<(), io::Error> {
-    let mut buffer = [0u8; size_of::<PongInputState>()];
+mod config;
+mod logging;
+mod metrics;
+mod referee;
+mod relay_match;
+mod shutdown;
+use config::RelayConfig;
+use metrics::{ConnectionGuard, Metrics};
+use relay_match::MatchEvent;
+use shutdown::Shutdown;
 
-    let mut to_return = Ok(());
-    loop {
-        match source_stream.read_exact(&mut buffer) {
-            Ok(_) => {
-                target_stream.write(&buffer).unwrap(); // TODO fix unclean error here where connection is forcibly closed
-             
-            }
-            Err(e) => match e.kind() {
-                io::ErrorKind::WouldBlock => break, // no more packets to funnel this way
-                _ => {
-                    println!(
-                        "While funnneling packets from hoster -> joiner, an error occured: {}",
-                        e
-                    );
-                    source_stream.shutdown(Shutdown::Both).unwrap();
-                    to_return = Err(e);
-                    break;
-                }
-            },
-        }
-    }
-    
-    to_return
+// match ids only exist to tie log lines from the same match together
+static NEXT_MATCH_ID: AtomicU64 = AtomicU64::new(0);
+
+// the joining client's stream travels to the lobby thread along with its guard so the
+// connection stays counted until the match is over
+type LobbyTransmitter = mpsc::Sender<(TcpStream, ConnectionGuard)>;
+
+/// Everything shared between the threads handling connections
+pub struct RelayState {
+    // Hashmap is probably the wrong datastructure to use for this problem,
+    // I don't care about the value of the keys, I just need each key to be
+    // unique and be able to add/remove keys at will.
+    lobby_to_host_transmitter: Mutex<HashMap<i32, LobbyTransmitter>>,
+    // running matches by lobby code, so reconnecting players can find their match
+    matches: Mutex<HashMap<i32, mpsc::Sender<MatchEvent>>>,
+    pub metrics: Arc<Metrics>,
+    pub shutdown: Arc<Shutdown>,
+    pub config: RelayConfig,
 }
 
-fn handle_client(
-    mut stream: TcpStream,
-    lobby_to_host_transmitter: Arc<Mutex<HashMap<i32, mpsc::Sender<TcpStream>>>>,
-) {
+fn handle_client(mut stream: TcpStream, connection_guard: ConnectionGuard, state: Arc<RelayState>) {
+    let metrics = &state.metrics;
     let mut data = [0 as u8; 5];
-    let mut tx: Option<mpsc::Sender<TcpStream>> = None;
-    let mut rx: Option<mpsc::Receiver<TcpStream>> = None;
+    let mut tx: Option<LobbyTransmitter> = None;
+    let mut rx: Option<mpsc::Receiver<(TcpStream, ConnectionGuard)>> = None;
+    let mut lobby_created_at: Option<time::Instant> = None;
     let mut my_lobby_code: Option<i32> = None;
     loop {
         match stream.read(&mut data) {
             Ok(size) => {
                 if size != 5 {
-                    println!("Got funky data size back (endl?), size: {}", size);
+                    warn!(size, "Got funky data size back (endl?)");
                     break;
-                } else if data[0] == 1 {
+                } else if data[0] == protocol::CREATE_LOBBY_COMMAND {
+                    if state.shutdown.is_requested() {
+                        info!("Refused to create a lobby, shutting down");
+                        stream
+                            .write_all(&protocol::LOBBY_CREATE_FAILED.to_le_bytes())
+                            .unwrap_or_default();
+                        break;
+                    }
+
                     // create lobby command
-                    println!("Creating lobby...");
+                    debug!("Creating lobby...");
                     let (new_tx, new_rx) = mpsc::channel();
                     tx = Some(new_tx);
                     rx = Some(new_rx);
-                    let mut dict = lobby_to_host_transmitter.lock().unwrap();
+                    let mut dict = state.lobby_to_host_transmitter.lock().unwrap();
                     let tx_into_sender = tx.as_ref().unwrap().clone();
-                    let new_lobby_id = rand::random::<i32>(); // TODO I don't check for lobby id conflicts here
+                    let mut new_lobby_id = rand::random::<i32>(); // TODO I don't check for lobby id conflicts here
+                    while new_lobby_id == protocol::LOBBY_CREATE_FAILED {
+                        new_lobby_id = rand::random::<i32>();
+                    }
                     (*dict).insert(new_lobby_id, tx_into_sender);
-                    println!("Created lobby with ID: {}", new_lobby_id);
+                    metrics.open_lobbies.fetch_add(1, Ordering::Relaxed);
+                    lobby_created_at = Some(time::Instant::now());
+                    info!(lobby = new_lobby_id, "Created lobby");
                     let encoded_lobby_id = new_lobby_id.to_le_bytes();
-                    stream.write(&encoded_lobby_id).unwrap(); // TODO should probably cleanly handle failing to send lobby code
+                    stream.write_all(&encoded_lobby_id).unwrap_or_default(); // TODO should probably cleanly handle failing to send lobby code
                     my_lobby_code = Some(new_lobby_id);
                     break;
-                } else if data[0] == 2 {
+                } else if data[0] == protocol::JOIN_LOBBY_COMMAND {
                     // join lobby command
 
                     let received_lobby_code =
                         i32::from_le_bytes([data[1], data[2], data[3], data[4]]);
-                    println!(
-                        "Attempting to connect a client to lobby: {}",
-                        received_lobby_code
-                    );
-                    let dict = lobby_to_host_transmitter.lock().unwrap();
-                    if (*dict).contains_key(&received_lobby_code) {
-                        println!(
-                        "Lobby exists! Saying OK then Sending my stream to that lobby thread..."
-                    );
-                        stream.write(&200i32.to_le_bytes()).unwrap();
+                    debug!(lobby = received_lobby_code, "Attempting to join lobby");
+                    let dict = state.lobby_to_host_transmitter.lock().unwrap();
+                    if state.shutdown.is_requested() {
+                        info!(lobby = received_lobby_code, "Refused join, shutting down");
+                        metrics.rejected_joins.fetch_add(1, Ordering::Relaxed);
+                        stream
+                            .write_all(&protocol::JOIN_SERVER_SHUTTING_DOWN.to_le_bytes())
+                            .unwrap_or_default();
+                        break;
+                    } else if (*dict).contains_key(&received_lobby_code) {
+                        info!(
+                            lobby = received_lobby_code,
+                            "Lobby exists, handing the connection to the lobby thread"
+                        );
+                        stream
+                            .write_all(&protocol::JOIN_OK.to_le_bytes())
+                            .unwrap_or_default();
                         (*dict)
                             .get(&received_lobby_code)
                             .unwrap()
-                            .send(stream)
+                            .send((stream, connection_guard))
                             .unwrap();
 
                         // returning from the entire function here so the borrow checker allows
                         // the sending of my stream to the dict
                         return;
                     } else {
-                        println!("Lobby does not exist...");
-                        stream.write(&400i32.to_le_bytes()).unwrap();
+                        warn!(
+                            lobby = received_lobby_code,
+                            "Rejected join, lobby does not exist"
+                        );
+                        metrics.rejected_joins.fetch_add(1, Ordering::Relaxed);
+                        stream
+                            .write_all(&protocol::JOIN_NO_SUCH_LOBBY.to_le_bytes())
+                            .unwrap_or_default();
+                    }
+                } else if data[0] == protocol::RECONNECT_COMMAND {
+                    // reconnect command, the rest of the request follows the usual 5 bytes
+                    let mut rest = [0u8; ReconnectRequest::SIZE - 5];
+                    if let Err(e) = stream.read_exact(&mut rest) {
+                        warn!(error = %e, "Incomplete reconnect request");
+                        break;
+                    }
+                    let request = ReconnectRequest::decode(&data, &rest);
+                    info!(lobby = request.lobby_code, "Player reconnecting");
+
+                    // reconnects are still allowed while shutting down so matches can finish
+                    let matches = state.matches.lock().unwrap();
+                    match matches.get(&request.lobby_code) {
+                        Some(match_transmitter) => {
+                            match_transmitter
+                                .send(MatchEvent::Reconnect {
+                                    stream,
+                                    connection_guard,
+                                    request,
+                                })
+                                .unwrap_or_default();
+                            return;
+                        }
+                        None => {
+                            warn!(
+                                lobby = request.lobby_code,
+                                "Rejected reconnect, no match running in that lobby"
+                            );
+                            stream
+                                .write_all(&protocol::RECONNECT_REJECTED.to_le_bytes())
+                                .unwrap_or_default();
+                            break;
+                        }
                     }
+                } else if data[0] == protocol::SPECTATE_COMMAND {
+                    let lobby_code = i32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+                    let matches = state.matches.lock().unwrap();
+                    let response = if state.shutdown.is_requested() {
+                        protocol::JOIN_SERVER_SHUTTING_DOWN
+                    } else if let Some(match_transmitter) = matches.get(&lobby_code) {
+                        info!(lobby = lobby_code, "Spectator joining match");
+                        match_transmitter
+                            .send(MatchEvent::Spectate {
+                                stream,
+                                connection_guard,
+                            })
+                            .unwrap_or_default();
+                        return;
+                    } else {
+                        protocol::SPECTATE_NO_SUCH_MATCH
+                    };
+                    warn!(lobby = lobby_code, response, "Rejected spectator");
+                    stream
+                        .write_all(&response.to_le_bytes())
+                        .unwrap_or_default();
+                    break;
                 } else {
-                    println!("Weird data that's not a known command: {}", data[0]);
+                    warn!(command = data[0], "Weird data that's not a known command");
                     break;
                 }
             }
-            Err(_) => {
-                println!(
-                    "An error occurred, terminating connection with {}",
-                    stream.peer_addr().unwrap()
-                );
-                stream.shutdown(Shutdown::Both).unwrap();
+            Err(e) => {
+                error!(error = %e, "Read failed, terminating connection");
+                stream.shutdown(NetShutdown::Both).unwrap();
                 break;
             }
         }
@@ -119,67 +202,169 @@ fn handle_client(
     if tx.is_some() && rx.is_some() {
         // If these are some, I am a thread that is managing a lobby and waiting for somebody to join
         // from the shared dict. Block on my channel until I receive a stream to connect with
-        println!("Waiting for client to connect...");
-        let mut other_stream = rx.unwrap().recv().unwrap(); // TODO need some way to timeout on this
+        let lobby_code = my_lobby_code.unwrap();
+        let _lobby_span = info_span!("lobby", code = lobby_code).entered();
+        debug!("Waiting for client to connect...");
+        let rx = rx.unwrap();
+        // TODO need some way to timeout on this
+        let (other_stream, other_connection_guard) = loop {
+            match rx.recv_timeout(time::Duration::from_millis(100)) {
+                Ok(joined) => break joined,
+                Err(_) => {
+                    if state.shutdown.is_requested() {
+                        close_lobby_for_shutdown(stream, &rx, lobby_code, &state);
+                        return;
+                    }
+                }
+            }
+        };
+        metrics.observe_lobby_wait(lobby_created_at.unwrap().elapsed());
 
-        // let the inviter know that somebody has joined and they can start funneling packets now
-        println!("Client connected! Letting the host know...");
-        stream.write(&[1]).unwrap();
+        let _match_span = info_span!(
+            "match",
+            id = NEXT_MATCH_ID.fetch_add(1, Ordering::Relaxed),
+            joiner = %other_stream.peer_addr().unwrap()
+        )
+        .entered();
 
         // Now that I have both streams, I can funnel packets back and forth. First I remove
-        // my lobby from the dict, as I am no longer waiting for a connection with recv
-        let mut dict = lobby_to_host_transmitter.lock().unwrap();
-        (*dict).remove_entry(&my_lobby_code.unwrap()).unwrap();
-
-        // Don't block while polling between two sockets as reading/writing
-        // from both with separate threads is not supported
-        stream.set_nonblocking(true).unwrap();
-        other_stream.set_nonblocking(true).unwrap();
-
-        println!("Funneling packets between two clients in loop...");
-        let mut e;
-        loop {
-            e = funnel_packets(&mut stream, &mut other_stream);
-            if e.is_err() {
-                break;
-            }
-            e = funnel_packets(&mut other_stream, &mut stream);
-            if e.is_err() {
-                break;
-            }
-            thread::sleep(time::Duration::from_millis(1));
-        }
+        // my lobby from the dict, as I am no longer waiting for a connection with recv, and
+        // make the match findable for reconnects
+        let mut dict = state.lobby_to_host_transmitter.lock().unwrap();
+        (*dict).remove_entry(&lobby_code).unwrap();
+        drop(dict);
+        metrics.open_lobbies.fetch_sub(1, Ordering::Relaxed);
+        let (match_tx, match_rx) = mpsc::channel();
+        state.matches.lock().unwrap().insert(lobby_code, match_tx);
+
+        metrics.active_matches.fetch_add(1, Ordering::Relaxed);
+        let reason = relay_match::run(
+            stream,
+            connection_guard,
+            other_stream,
+            other_connection_guard,
+            match_rx,
+            lobby_code,
+            &state,
+        );
+        state.matches.lock().unwrap().remove(&lobby_code);
+        metrics.active_matches.fetch_sub(1, Ordering::Relaxed);
+
+        info!(%reason, "Match over");
+    }
+}
+
+/// The relay is draining, so the lobby will never be joined. Also turns away a
+/// joiner that got in right before the lobby was taken out of the dict
+fn close_lobby_for_shutdown(
+    host_stream: TcpStream,
+    rx: &mpsc::Receiver<(TcpStream, ConnectionGuard)>,
+    lobby_code: i32,
+    state: &RelayState,
+) {
+    state
+        .lobby_to_host_transmitter
+        .lock()
+        .unwrap()
+        .remove_entry(&lobby_code);
+    state.metrics.open_lobbies.fetch_sub(1, Ordering::Relaxed);
 
-        println!("Stopped funneling packets: {}", e.err().unwrap());
+    let message = Message::ServerShuttingDown {
+        seconds_left: state.shutdown.seconds_left(),
+    };
+    let mut streams = vec![host_stream];
+    if let Ok((joiner_stream, _guard)) = rx.try_recv() {
+        streams.push(joiner_stream);
     }
+    for stream in streams {
+        if let Ok(mut connection) = Connection::new(stream) {
+            connection.send(&message).unwrap_or_default();
+        }
+    }
+    info!("Closed lobby, shutting down");
 }
 
 fn main() {
-    // Hashmap is probably the wrong datastructure to use for this problem,
-    // I don't care about the value of the keys, I just need each key to be
-    // unique and be able to add/remove keys at will.
-    let lobby_to_host_transmitter: Arc<Mutex<HashMap<i32, mpsc::Sender<TcpStream>>>> =
-        Arc::new(Mutex::new(HashMap::new()));
+    let config = match RelayConfig::from_args(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}\n{}", e, config::USAGE);
+            process::exit(2);
+        }
+    };
+    logging::init(config.log_json);
+    if let Some(dir) = &config.record_dir {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("can't create {}: {}", dir.display(), e);
+            process::exit(2);
+        }
+    }
+
+    let metrics = Arc::new(Metrics::new());
+    metrics::serve(Arc::clone(&metrics), metrics::METRICS_ADDR).unwrap();
+    info!(
+        "Serving metrics on http://{}/metrics",
+        metrics::METRICS_ADDR
+    );
+
+    let shutdown = Arc::new(Shutdown::new(config.shutdown_timeout));
+    shutdown::install_signal_handler(Arc::clone(&shutdown));
+
+    let state = Arc::new(RelayState {
+        lobby_to_host_transmitter: Mutex::new(HashMap::new()),
+        matches: Mutex::new(HashMap::new()),
+        metrics: Arc::clone(&metrics),
+        shutdown: Arc::clone(&shutdown),
+        config,
+    });
 
     let listener = TcpListener::bind(format!("0.0.0.0:{}", PORT)).unwrap();
+    // polled so the loop can notice a shutdown request in between connections
+    listener.set_nonblocking(true).unwrap();
     // accept connections and process them, spawning a new thread for each one
-    println!("Server listening on port {}", PORT);
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                println!("New connection: {}", stream.peer_addr().unwrap());
-                let reference_to_lobby_stuff = Arc::clone(&lobby_to_host_transmitter);
+    info!(port = PORT, "Server listening");
+    loop {
+        if shutdown.is_requested() {
+            let matches_left = metrics.active_matches.load(Ordering::Relaxed);
+            let lobbies_left = metrics.open_lobbies.load(Ordering::Relaxed);
+            if matches_left == 0 && lobbies_left == 0 {
+                info!("All matches and lobbies closed");
+                break;
+            }
+            // match threads close their own connections at the deadline, this is a last resort
+            let grace_period_over = shutdown
+                .deadline()
+                .map(|deadline| deadline.elapsed() > time::Duration::from_secs(1))
+                .unwrap_or(false);
+            if grace_period_over {
+                warn!(matches_left, lobbies_left, "Gave up waiting for matches");
+                break;
+            }
+        }
+
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                stream.set_nonblocking(false).unwrap();
+                let span = info_span!("connection", %peer);
+                span.in_scope(|| info!("New connection"));
+                let connection_guard = ConnectionGuard::new(Arc::clone(&metrics));
+                let reference_to_state = Arc::clone(&state);
                 thread::spawn(move || {
                     // connection succeeded
-                    handle_client(stream, reference_to_lobby_stuff);
+                    let _connection_span = span.entered();
+                    handle_client(stream, connection_guard, reference_to_state);
                 });
             }
-            Err(e) => {
-                println!("Error: {}", e);
-                /* connection failed */
-            }
+            Err(e) => match e.kind() {
+                io::ErrorKind::WouldBlock => thread::sleep(time::Duration::from_millis(50)),
+                _ => {
+                    error!(error = %e, "Failed to accept connection");
+                    /* connection failed */
+                }
+            },
         }
     }
     // close the socket server
     drop(listener);
+    info!("Relay shut down");
 }
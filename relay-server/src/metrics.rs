@@ -0,0 +1,274 @@
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tracing::warn;
+
+// only serve metrics to the machine the relay is running on, scrape it from there or tunnel in
+pub const METRICS_ADDR: &str = "127.0.0.1:9321";
+
+// upper bounds in seconds for the histograms, +Inf is implicit
+const LOBBY_WAIT_BUCKETS: [f64; 9] = [1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0];
+const RTT_BUCKETS: [f64; 8] = [0.01, 0.025, 0.05, 0.075, 0.1, 0.2, 0.5, 1.0];
+
+struct Histogram {
+    upper_bounds: &'static [f64],
+    bucket_counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(upper_bounds: &'static [f64]) -> Self {
+        Histogram {
+            upper_bounds,
+            bucket_counts: vec![0; upper_bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (i, upper_bound) in self.upper_bounds.iter().enumerate() {
+            if value <= *upper_bound {
+                self.bucket_counts[i] += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        for (upper_bound, count) in self.upper_bounds.iter().zip(self.bucket_counts.iter()) {
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, upper_bound, count).unwrap();
+        }
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count).unwrap();
+        writeln!(out, "{}_sum {}", name, self.sum).unwrap();
+        writeln!(out, "{}_count {}", name, self.count).unwrap();
+    }
+}
+
+/// Everything the relay reports about itself. Shared between every client thread
+/// and the thread serving the metrics endpoint
+pub struct Metrics {
+    pub active_connections: AtomicI64,
+    pub open_lobbies: AtomicI64,
+    pub active_matches: AtomicI64,
+    pub active_spectators: AtomicI64,
+    pub bytes_relayed: AtomicU64,
+    pub packets_relayed: AtomicU64,
+    pub rejected_joins: AtomicU64,
+    pub rejected_inputs: AtomicU64,
+    lobby_wait_seconds: Mutex<Histogram>,
+    player_rtt_seconds: Mutex<Histogram>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            active_connections: AtomicI64::new(0),
+            open_lobbies: AtomicI64::new(0),
+            active_matches: AtomicI64::new(0),
+            active_spectators: AtomicI64::new(0),
+            bytes_relayed: AtomicU64::new(0),
+            packets_relayed: AtomicU64::new(0),
+            rejected_joins: AtomicU64::new(0),
+            rejected_inputs: AtomicU64::new(0),
+            lobby_wait_seconds: Mutex::new(Histogram::new(&LOBBY_WAIT_BUCKETS)),
+            player_rtt_seconds: Mutex::new(Histogram::new(&RTT_BUCKETS)),
+        }
+    }
+
+    pub fn observe_lobby_wait(&self, wait: Duration) {
+        self.lobby_wait_seconds
+            .lock()
+            .unwrap()
+            .observe(wait.as_secs_f64());
+    }
+
+    pub fn observe_player_rtt(&self, rtt: Duration) {
+        self.player_rtt_seconds
+            .lock()
+            .unwrap()
+            .observe(rtt.as_secs_f64());
+    }
+
+    /// Renders every metric in the prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let gauges = [
+            (
+                "pong_relay_active_connections",
+                "TCP connections currently open to the relay",
+                &self.active_connections,
+            ),
+            (
+                "pong_relay_open_lobbies",
+                "Lobbies created and waiting for a player to join",
+                &self.open_lobbies,
+            ),
+            (
+                "pong_relay_active_matches",
+                "Matches currently having their packets relayed",
+                &self.active_matches,
+            ),
+            (
+                "pong_relay_active_spectators",
+                "Spectators currently watching a match",
+                &self.active_spectators,
+            ),
+        ];
+        for (name, help, value) in gauges.iter() {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} gauge", name).unwrap();
+            writeln!(out, "{} {}", name, value.load(Ordering::Relaxed)).unwrap();
+        }
+
+        let counters = [
+            (
+                "pong_relay_bytes_relayed_total",
+                "Bytes forwarded from one player to the other",
+                &self.bytes_relayed,
+            ),
+            (
+                "pong_relay_packets_relayed_total",
+                "Input packets forwarded from one player to the other",
+                &self.packets_relayed,
+            ),
+            (
+                "pong_relay_rejected_joins_total",
+                "Join requests for lobbies that do not exist",
+                &self.rejected_joins,
+            ),
+            (
+                "pong_relay_rejected_inputs_total",
+                "Inputs an authoritative relay refused as impossible",
+                &self.rejected_inputs,
+            ),
+        ];
+        for (name, help, value) in counters.iter() {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            writeln!(out, "{} {}", name, value.load(Ordering::Relaxed)).unwrap();
+        }
+
+        self.lobby_wait_seconds.lock().unwrap().render(
+            &mut out,
+            "pong_relay_lobby_wait_seconds",
+            "Time between a lobby being created and a player joining it",
+        );
+        self.player_rtt_seconds.lock().unwrap().render(
+            &mut out,
+            "pong_relay_player_rtt_seconds",
+            "Round trip time of heartbeats between the relay and players",
+        );
+
+        out
+    }
+}
+
+/// Counts an open connection in the metrics for as long as it is alive, so the
+/// gauge stays correct no matter which thread ends up dropping the stream
+pub struct ConnectionGuard {
+    metrics: Arc<Metrics>,
+}
+
+impl ConnectionGuard {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        metrics.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard { metrics }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> Result<(), io::Error> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+
+    let (status, body) = if method == "GET" && (path == "/metrics" || path == "/") {
+        ("200 OK", metrics.render())
+    } else {
+        ("404 Not Found", String::from("not found\n"))
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Serves the metrics over HTTP on its own thread. Requests are handled one at a
+/// time, scrapes are rare and tiny so there's no need for anything fancier
+pub fn serve(metrics: Arc<Metrics>, addr: &str) -> Result<(), io::Error> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = respond(stream, &metrics) {
+                        warn!(error = %e, "Failed to serve metrics request");
+                    }
+                }
+                Err(e) => {
+                    warn!(error = %e, "Failed to accept metrics connection");
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_histogram_buckets() {
+        let metrics = Metrics::new();
+        metrics.observe_lobby_wait(Duration::from_secs(3));
+        metrics.observe_lobby_wait(Duration::from_secs(45));
+        metrics.rejected_joins.fetch_add(2, Ordering::Relaxed);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("pong_relay_rejected_joins_total 2\n"));
+        assert!(rendered.contains("pong_relay_lobby_wait_seconds_bucket{le=\"1\"} 0\n"));
+        assert!(rendered.contains("pong_relay_lobby_wait_seconds_bucket{le=\"5\"} 1\n"));
+        assert!(rendered.contains("pong_relay_lobby_wait_seconds_bucket{le=\"60\"} 2\n"));
+        assert!(rendered.contains("pong_relay_lobby_wait_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(rendered.contains("pong_relay_lobby_wait_seconds_count 2\n"));
+        assert!(rendered.contains("pong_relay_player_rtt_seconds_count 0\n"));
+    }
+
+    #[test]
+    fn test_connection_guard() {
+        let metrics = Arc::new(Metrics::new());
+        let guard = ConnectionGuard::new(Arc::clone(&metrics));
+        assert_eq!(metrics.active_connections.load(Ordering::Relaxed), 1);
+        drop(guard);
+        assert_eq!(metrics.active_connections.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use common::config::ConfigError;
+use common::protocol::{Message, SNAPSHOT_STATE_SIZE};
+use common::sim::{PongGameConfig, PongGameState};
+use common::validation::InputViolation;
+use common::PongInputState;
+
+// how often the referee takes a snapshot for spectators who join late, same as the clients
+const SNAPSHOT_INTERVAL_FRAMES: u32 = 60;
+
+/// Why a player loses the match without playing it out
+#[derive(Clone, PartialEq, Debug)]
+pub enum Forfeit {
+    Input(InputViolation),
+    // only the host sends settings, the referee would have had to simulate with them
+    Settings(ConfigError),
+}
+
+impl fmt::Display for Forfeit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Forfeit::Input(violation) => write!(f, "sending an impossible input: {}", violation),
+            Forfeit::Settings(e) => write!(f, "sending unusable game settings: {}", e),
+        }
+    }
+}
+
+/// Runs the same simulation the players do on the inputs the relay has from both of them,
+/// so the relay has its own idea of the score that no client can fudge
+pub struct Referee {
+    game_state: PongGameState,
+    seed: u64,
+    // inputs waiting for the other player's input for the same frame, 0 is the host on the left
+    pending: [VecDeque<PongInputState>; 2],
+    next_frame: u32,
+    // the first player caught sending something impossible, they lose the match
+    pub forfeit: Option<(usize, Forfeit)>,
+}
+
+impl Referee {
+    pub fn new(seed: u64) -> Self {
+        Referee {
+            game_state: PongGameState::with_seed(seed),
+            seed,
+            pending: [VecDeque::new(), VecDeque::new()],
+            next_frame: 0,
+            forfeit: None,
+        }
+    }
+
+    /// Starts over with the host's settings, which arrive after the referee is made but before
+    /// any inputs. They have to have been validated already, the simulation panics on some
+    /// settings that don't
+    pub fn configure(&mut self, config: PongGameConfig) {
+        debug_assert_eq!(self.next_frame, 0);
+        self.game_state = PongGameState::with_config(config, self.seed);
+    }
+
+    /// The settings being simulated with
+    pub fn config(&self) -> &PongGameConfig {
+        &self.game_state.config
+    }
+
+    /// Takes an input that has already been validated and simulates every frame both
+    /// players' inputs are in for. Returns a snapshot if one of those frames was due one
+    pub fn add_input(
+        &mut self,
+        side: usize,
+        input: PongInputState,
+    ) -> Option<(u32, [u8; SNAPSHOT_STATE_SIZE])> {
+        self.pending[side].push_back(input);
+
+        let mut snapshot = None;
+        while self.pending.iter().all(|inputs| !inputs.is_empty()) {
+            let inputs = [
+                self.pending[0].pop_front().unwrap(),
+                self.pending[1].pop_front().unwrap(),
+            ];
+            debug_assert!(inputs.iter().all(|input| input.frame == self.next_frame));
+            self.game_state.process_logic(&inputs);
+            if self.next_frame.is_multiple_of(SNAPSHOT_INTERVAL_FRAMES) {
+                snapshot = Some((self.next_frame, self.game_state.to_bytes()));
+            }
+            self.next_frame += 1;
+        }
+        snapshot
+    }
+
+    pub fn reject(&mut self, side: usize, reason: Forfeit) {
+        if self.forfeit.is_none() {
+            self.forfeit = Some((side, reason));
+        }
+    }
+
+    /// Whoever has won the match, going by the inputs in so far
+    pub fn winner(&self) -> Option<usize> {
+        self.game_state.winner()
+    }
+
+    /// The official score as of the last frame both players' inputs were in for
+    pub fn result(&self) -> Message {
+        Message::MatchResult {
+            frame: self.next_frame.saturating_sub(1),
+            left_score: self.game_state.left_score.value,
+            right_score: self.game_state.right_score.value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulates_when_both_inputs_are_in() {
+        let mut referee = Referee::new(3);
+        let mut expected = PongGameState::with_seed(3);
+
+        // the host is a few frames ahead, nothing can be simulated until the joiner catches up
+        for frame in 0..3 {
+            assert_eq!(referee.add_input(0, PongInputState::new(frame, 1.0)), None);
+        }
+        assert_eq!(referee.next_frame, 0);
+
+        // frame 0 is simulated and due a snapshot
+        let snapshot = referee.add_input(1, PongInputState::new(0, -1.0));
+        expected.process_logic(&[PongInputState::new(0, 1.0), PongInputState::new(0, -1.0)]);
+        assert_eq!(snapshot, Some((0, expected.to_bytes())));
+
+        assert_eq!(referee.add_input(1, PongInputState::new(1, -1.0)), None);
+        expected.process_logic(&[PongInputState::new(1, 1.0), PongInputState::new(1, -1.0)]);
+        assert_eq!(referee.next_frame, 2);
+        assert_eq!(referee.game_state, expected);
+    }
+
+    #[test]
+    fn test_plays_with_the_hosts_settings() {
+        let config = PongGameConfig {
+            ball_speed: 700.0,
+            ..common::sim::GAME_CONFIG
+        };
+        let mut referee = Referee::new(5);
+        referee.configure(config);
+        let mut expected = PongGameState::with_config(config, 5);
+
+        for frame in 0..200 {
+            referee.add_input(0, PongInputState::new(frame, 1.0));
+            referee.add_input(1, PongInputState::new(frame, 0.0));
+            expected.process_logic(&[
+                PongInputState::new(frame, 1.0),
+                PongInputState::new(frame, 0.0),
+            ]);
+        }
+        assert_eq!(referee.game_state, expected);
+        assert_eq!(referee.game_state.config, config);
+    }
+}
@@ -0,0 +1,775 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time;
+use std::time::SystemTime;
+
+use tracing::{info, warn};
+
+use common::latency::Heartbeat;
+use common::protocol::{self, Connection, Message, SNAPSHOT_STATE_SIZE};
+use common::replay::{unix_millis, Replay};
+use common::sim::PongGameConfig;
+use common::validation;
+use common::PongInputState;
+
+use crate::metrics::{ConnectionGuard, Metrics};
+use crate::referee::{Forfeit, Referee};
+use crate::RelayState;
+
+// how many inputs from each player are kept around to resend after a reconnect. Clients
+// never predict further ahead than their rollback window so this is plenty
+const INPUT_HISTORY_LEN: usize = 512;
+
+// the newest snapshot a player (or the referee) sent, the frame it was taken after and the serialized state
+type Snapshot = (u32, [u8; SNAPSHOT_STATE_SIZE]);
+
+/// The game settings the host sent and the hash the joiner answered with. Kept around for
+/// players who reconnect before the exchange got through, and for spectators
+#[derive(Default)]
+struct AgreedSettings {
+    config: Option<PongGameConfig>,
+    joiner_hash: Option<u64>,
+}
+
+/// Everything about a running match besides the players and the referee, most of it kept
+/// to catch up spectators who join late
+struct MatchState {
+    seed: u64,
+    settings: AgreedSettings,
+    latest_snapshot: Option<Snapshot>,
+    spectators: Vec<Spectator>,
+    metrics: Arc<Metrics>,
+}
+
+/// Sent to a running match's thread by other connections' threads
+pub enum MatchEvent {
+    Reconnect {
+        stream: TcpStream,
+        connection_guard: ConnectionGuard,
+        request: protocol::ReconnectRequest,
+    },
+    Spectate {
+        stream: TcpStream,
+        connection_guard: ConnectionGuard,
+    },
+}
+
+/// Watches the match without playing in it, gets both players' inputs
+struct Spectator {
+    connection: Connection,
+    _connection_guard: ConnectionGuard,
+    metrics: Arc<Metrics>,
+}
+
+impl Drop for Spectator {
+    fn drop(&mut self) {
+        self.metrics
+            .active_spectators
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct Player {
+    name: &'static str, // for logs, "host" or "joiner"
+    connection: Option<Connection>,
+    connection_guard: Option<ConnectionGuard>,
+    session_token: u64,
+    disconnected_at: Option<time::Instant>,
+    heartbeat: Heartbeat,
+    // inputs received from this player, oldest first
+    input_history: VecDeque<PongInputState>,
+    // every input this player sent, only kept when the relay records matches
+    recorded_inputs: Option<Vec<PongInputState>>,
+}
+
+impl Player {
+    fn new(
+        name: &'static str,
+        stream: TcpStream,
+        connection_guard: ConnectionGuard,
+        recording: bool,
+    ) -> Self {
+        Player {
+            name,
+            connection: Some(Connection::new(stream).unwrap()),
+            connection_guard: Some(connection_guard),
+            session_token: rand::random::<u64>(),
+            disconnected_at: None,
+            heartbeat: Heartbeat::new(time::Instant::now()),
+            input_history: VecDeque::with_capacity(INPUT_HISTORY_LEN),
+            recorded_inputs: recording.then(Vec::new),
+        }
+    }
+
+    fn next_frame_expected(&self) -> u32 {
+        self.input_history
+            .back()
+            .map(|input| input.frame + 1)
+            .unwrap_or(0)
+    }
+
+    /// A failed send is noticed on the next receive, no need to handle it here
+    fn send(&mut self, message: &Message) {
+        if let Some(connection) = self.connection.as_mut() {
+            connection.send(message).unwrap_or_default();
+        }
+    }
+}
+
+/// Splits the players into the one on `side` and their opponent
+fn player_and_opponent(players: &mut [Player; 2], side: usize) -> (&mut Player, &mut Player) {
+    let (left, right) = players.split_at_mut(1);
+    if side == 0 {
+        (&mut left[0], &mut right[0])
+    } else {
+        (&mut right[0], &mut left[0])
+    }
+}
+
+fn disconnect(players: &mut [Player; 2], side: usize, reason: &str, state: &RelayState) {
+    let (player, opponent) = player_and_opponent(players, side);
+    info!(
+        player = player.name,
+        reason,
+        grace_secs = state.config.reconnect_grace.as_secs(),
+        "Player disconnected, waiting for them to reconnect"
+    );
+    player.connection = None;
+    player.connection_guard = None;
+    player.disconnected_at = Some(time::Instant::now());
+    opponent.send(&Message::OpponentDisconnected {
+        grace_seconds: state.config.reconnect_grace.as_secs() as u32,
+    });
+}
+
+/// Forwards every input that has arrived from the player on `side` to their opponent,
+/// keeping a copy around in case the opponent has to reconnect and catch up. Heartbeats
+/// are answered here too. With a referee, inputs are checked and simulated before
+/// they're forwarded
+fn relay_messages(
+    players: &mut [Player; 2],
+    match_state: &mut MatchState,
+    referee: &mut Option<Referee>,
+    side: usize,
+) -> Result<(), String> {
+    let MatchState {
+        settings,
+        latest_snapshot,
+        spectators,
+        metrics,
+        ..
+    } = match_state;
+    let (player, opponent) = player_and_opponent(players, side);
+    let (messages, error) = match player.connection.as_mut() {
+        Some(connection) => connection.receive(),
+        None => return Ok(()),
+    };
+
+    let now = time::Instant::now();
+    for message in messages {
+        player.heartbeat.heard_from(now);
+        match message {
+            Message::Input(input) => {
+                if input.frame < player.next_frame_expected() {
+                    // resent after a reconnect, but it made it through the first time
+                    continue;
+                }
+                if let Some(referee) = referee.as_mut() {
+                    let newest_allowed = opponent.next_frame_expected()
+                        + referee.config().max_frames_ahead_of_opponent();
+                    if let Err(violation) = validation::validate_input(
+                        &input,
+                        player.next_frame_expected(),
+                        newest_allowed,
+                    ) {
+                        warn!(
+                            player = player.name,
+                            %violation,
+                            ?input,
+                            "Rejected an impossible input"
+                        );
+                        metrics.rejected_inputs.fetch_add(1, Ordering::Relaxed);
+                        referee.reject(side, Forfeit::Input(violation));
+                        break;
+                    }
+                    if let Some(snapshot) = referee.add_input(side, input) {
+                        *latest_snapshot = Some(snapshot);
+                    }
+                }
+                player.input_history.push_back(input);
+                if player.input_history.len() > INPUT_HISTORY_LEN {
+                    player.input_history.pop_front();
+                }
+                if let Some(recorded_inputs) = player.recorded_inputs.as_mut() {
+                    recorded_inputs.push(input);
+                }
+
+                if opponent.connection.is_some() {
+                    opponent.send(&message);
+                    metrics
+                        .bytes_relayed
+                        .fetch_add(message.encode().len() as u64, Ordering::Relaxed);
+                    metrics.packets_relayed.fetch_add(1, Ordering::Relaxed);
+                }
+                let spectator_message = Message::PlayerInput {
+                    side: side as u8,
+                    input,
+                };
+                for spectator in spectators.iter_mut() {
+                    // a failed send is noticed when the spectators are polled
+                    spectator
+                        .connection
+                        .send(&spectator_message)
+                        .unwrap_or_default();
+                }
+            }
+            Message::Snapshot { .. } if referee.is_some() => {
+                // the referee's own snapshots are the ones to trust
+            }
+            Message::Snapshot { frame, state } => {
+                // both players send them, whichever is newest is the most useful
+                if latest_snapshot.is_none_or(|(newest, _)| frame > newest) {
+                    *latest_snapshot = Some((frame, state));
+                }
+            }
+            Message::MatchConfig { config } if side == 0 && settings.config.is_none() => {
+                // kept for whoever reconnects or starts spectating before the exchange is over
+                info!(hash = config.config_hash(), "Host sent the game settings");
+                if let Err(e) = config.validate() {
+                    warn!(player = player.name, error = %e, "Host sent unusable game settings");
+                    // the referee can't simulate them, and without one the joiner turns them
+                    // down and ends the match itself
+                    if let Some(referee) = referee.as_mut() {
+                        referee.reject(side, Forfeit::Settings(e));
+                        break;
+                    }
+                }
+                if let Some(referee) = referee.as_mut() {
+                    referee.configure(config);
+                }
+                settings.config = Some(config);
+                opponent.send(&message);
+                for spectator in spectators.iter_mut() {
+                    spectator.connection.send(&message).unwrap_or_default();
+                }
+            }
+            Message::ConfigHash { hash } if side == 1 => {
+                settings.joiner_hash = Some(hash);
+                opponent.send(&message);
+            }
+            Message::Ping { sent_at } => player.send(&Message::Pong { sent_at }),
+            Message::Pong { sent_at } => {
+                metrics.observe_player_rtt(player.heartbeat.pong_received(sent_at, now));
+            }
+            Message::PeerPing { .. } | Message::PeerPong { .. } => {
+                // measured end to end, so not worth keeping around for a reconnect
+                if opponent.connection.is_some() {
+                    opponent.send(&message);
+                    metrics
+                        .bytes_relayed
+                        .fetch_add(message.encode().len() as u64, Ordering::Relaxed);
+                }
+            }
+            _ => warn!(
+                player = player.name,
+                ?message,
+                "Client sent a relay-only message"
+            ),
+        }
+    }
+    if let Some(opponent_connection) = opponent.connection.as_mut() {
+        opponent_connection.flush().unwrap_or_default();
+    }
+
+    match error {
+        Some(e) => Err(e.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Splices a reconnecting player back into the match and catches them up on
+/// the inputs their opponent sent while they were gone
+fn reconnect(
+    players: &mut [Player; 2],
+    mut stream: TcpStream,
+    connection_guard: ConnectionGuard,
+    request: protocol::ReconnectRequest,
+    match_state: &MatchState,
+) {
+    let MatchState {
+        settings, metrics, ..
+    } = match_state;
+    let side = match players
+        .iter()
+        .position(|player| player.session_token == request.session_token)
+    {
+        Some(side) => side,
+        None => {
+            warn!("Rejected reconnect, wrong session token");
+            stream
+                .write_all(&protocol::RECONNECT_REJECTED.to_le_bytes())
+                .unwrap_or_default();
+            return;
+        }
+    };
+    if stream
+        .write_all(&protocol::RECONNECT_OK.to_le_bytes())
+        .is_err()
+    {
+        return;
+    }
+    let mut connection = match Connection::new(stream) {
+        Ok(connection) => connection,
+        Err(_) => return,
+    };
+
+    let (player, opponent) = player_and_opponent(players, side);
+    info!(
+        player = player.name,
+        next_frame_expected = request.next_frame_expected,
+        "Player reconnected"
+    );
+
+    // if the old connection is still around it's half open, the new one wins
+    connection
+        .send(&Message::Resumed {
+            next_frame_expected: player.next_frame_expected(),
+        })
+        .unwrap_or_default();
+    // whichever half of the settings exchange the player might have missed
+    let missed_settings = match side {
+        0 => settings
+            .joiner_hash
+            .map(|hash| Message::ConfigHash { hash }),
+        _ => settings
+            .config
+            .map(|config| Message::MatchConfig { config }),
+    };
+    if let Some(message) = missed_settings {
+        connection.send(&message).unwrap_or_default();
+    }
+    if let Some(oldest) = opponent.input_history.front() {
+        if oldest.frame > request.next_frame_expected {
+            warn!(
+                oldest_frame = oldest.frame,
+                "Reconnected player missed inputs that aren't kept anymore"
+            );
+        }
+    }
+    for input in opponent
+        .input_history
+        .iter()
+        .filter(|input| input.frame >= request.next_frame_expected)
+    {
+        let message = Message::Input(*input);
+        connection.send(&message).unwrap_or_default();
+        metrics
+            .bytes_relayed
+            .fetch_add(message.encode().len() as u64, Ordering::Relaxed);
+        metrics.packets_relayed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    player.connection = Some(connection);
+    player.connection_guard = Some(connection_guard);
+    player.disconnected_at = None;
+    player.heartbeat = Heartbeat::new(time::Instant::now());
+    opponent.send(&Message::OpponentReconnected);
+}
+
+/// Catches a new spectator up from the latest snapshot, or from the start of the match if
+/// there isn't a usable one yet, then adds them to the match
+fn add_spectator(
+    players: &[Player; 2],
+    match_state: &mut MatchState,
+    mut stream: TcpStream,
+    connection_guard: ConnectionGuard,
+) {
+    let MatchState {
+        seed,
+        settings,
+        latest_snapshot,
+        spectators,
+        metrics,
+    } = match_state;
+    if stream
+        .write_all(&protocol::SPECTATE_OK.to_le_bytes())
+        .is_err()
+    {
+        return;
+    }
+    let mut connection = match Connection::new(stream) {
+        Ok(connection) => connection,
+        Err(_) => return,
+    };
+    connection
+        .send(&Message::MatchSeed { seed: *seed })
+        .unwrap_or_default();
+    if let Some(config) = settings.config {
+        connection
+            .send(&Message::MatchConfig { config })
+            .unwrap_or_default();
+    }
+
+    // the snapshot is only any good if every input after it is still around
+    let snapshot = latest_snapshot.filter(|(frame, _)| {
+        players.iter().all(|player| {
+            player
+                .input_history
+                .front()
+                .is_none_or(|oldest| oldest.frame <= frame + 1)
+        })
+    });
+    let first_frame = match snapshot {
+        Some((frame, state)) => {
+            connection
+                .send(&Message::Snapshot { frame, state })
+                .unwrap_or_default();
+            frame + 1
+        }
+        None => 0,
+    };
+
+    for (side, player) in players.iter().enumerate() {
+        if let Some(oldest) = player.input_history.front() {
+            if oldest.frame > first_frame {
+                warn!(
+                    player = player.name,
+                    oldest_frame = oldest.frame,
+                    first_frame,
+                    "Spectator joined too late to get every input"
+                );
+            }
+        }
+        for input in player
+            .input_history
+            .iter()
+            .filter(|input| input.frame >= first_frame)
+        {
+            connection
+                .send(&Message::PlayerInput {
+                    side: side as u8,
+                    input: *input,
+                })
+                .unwrap_or_default();
+        }
+    }
+
+    metrics.active_spectators.fetch_add(1, Ordering::Relaxed);
+    spectators.push(Spectator {
+        connection,
+        _connection_guard: connection_guard,
+        metrics: Arc::clone(metrics),
+    });
+    info!(
+        spectators = spectators.len(),
+        first_frame, "Spectator joined"
+    );
+}
+
+/// Tells everybody the official score when an authoritative match ends
+fn publish_result(
+    players: &mut [Player; 2],
+    spectators: &mut [Spectator],
+    referee: &Option<Referee>,
+) {
+    let referee = match referee {
+        Some(referee) => referee,
+        None => return,
+    };
+    let result = referee.result();
+    info!(?result, "Official result");
+    for player in players.iter_mut() {
+        player.send(&result);
+    }
+    for spectator in spectators.iter_mut() {
+        spectator.connection.send(&result).unwrap_or_default();
+    }
+}
+
+/// Relays inputs between the two players until the match is over, returning why it ended
+pub fn run(
+    host_stream: TcpStream,
+    host_connection_guard: ConnectionGuard,
+    joiner_stream: TcpStream,
+    joiner_connection_guard: ConnectionGuard,
+    events: mpsc::Receiver<MatchEvent>,
+    lobby_code: i32,
+    state: &RelayState,
+) -> String {
+    let started_at = SystemTime::now();
+    // Don't block while polling between two sockets as reading/writing
+    // from both with separate threads is not supported
+    let mut players = [
+        Player::new(
+            "host",
+            host_stream,
+            host_connection_guard,
+            state.config.record_dir.is_some(),
+        ),
+        Player::new(
+            "joiner",
+            joiner_stream,
+            joiner_connection_guard,
+            state.config.record_dir.is_some(),
+        ),
+    ];
+
+    // let the inviter know that somebody has joined and they can start funneling packets now
+    info!("Client joined, letting the host know");
+    players[0].send(&Message::OpponentJoined);
+    // picked here so neither player can choose it
+    let seed = rand::random::<u64>();
+    for player in players.iter_mut() {
+        let token = player.session_token;
+        player.send(&Message::SessionToken { token });
+        player.send(&Message::MatchSeed { seed });
+    }
+
+    info!("Match started, funneling packets between the two clients");
+    let mut match_state = MatchState {
+        seed,
+        settings: AgreedSettings::default(),
+        latest_snapshot: None,
+        spectators: Vec::new(),
+        metrics: Arc::clone(&state.metrics),
+    };
+    let mut referee = state.config.authoritative.then(|| Referee::new(seed));
+    let mut told_clients_about_shutdown = false;
+    let reason = 'relay: loop {
+        while let Ok(event) = events.try_recv() {
+            match event {
+                MatchEvent::Reconnect {
+                    stream,
+                    connection_guard,
+                    request,
+                } => reconnect(
+                    &mut players,
+                    stream,
+                    connection_guard,
+                    request,
+                    &match_state,
+                ),
+                MatchEvent::Spectate {
+                    stream,
+                    connection_guard,
+                } => add_spectator(&players, &mut match_state, stream, connection_guard),
+            }
+        }
+
+        for side in 0..players.len() {
+            if let Err(reason) = relay_messages(&mut players, &mut match_state, &mut referee, side)
+            {
+                disconnect(&mut players, side, &reason, state);
+            }
+        }
+
+        if let Some((side, reason)) = referee.as_ref().and_then(|referee| referee.forfeit.clone()) {
+            publish_result(&mut players, &mut match_state.spectators, &referee);
+            break format!("{} forfeited by {}", players[side].name, reason);
+        }
+
+        // the players leave for the results once the match is won, there's nothing to wait
+        // for them to reconnect to
+        if let Some(winner) = referee.as_ref().and_then(|referee| referee.winner()) {
+            if players.iter().any(|player| player.connection.is_none()) {
+                publish_result(&mut players, &mut match_state.spectators, &referee);
+                break format!("{} won the match", players[winner].name);
+            }
+        }
+
+        // spectators have nothing to say, reading only notices when they leave
+        match_state.spectators.retain_mut(|spectator| {
+            let (_, error) = spectator.connection.receive();
+            match error.or_else(|| spectator.connection.flush().err()) {
+                Some(e) => {
+                    info!(error = %e, "Spectator left");
+                    false
+                }
+                None => true,
+            }
+        });
+
+        // a connection that has gone quiet is as good as dropped, the player can reconnect
+        let now = time::Instant::now();
+        for side in 0..players.len() {
+            let player = &mut players[side];
+            if player.connection.is_none() {
+                continue;
+            }
+            if player.heartbeat.timed_out(now) {
+                disconnect(&mut players, side, "heartbeat timed out", state);
+            } else if let Some(sent_at) = player.heartbeat.ping_due(now) {
+                player.send(&Message::Ping { sent_at });
+            }
+        }
+
+        for side in 0..players.len() {
+            let player = &players[side];
+            if let Some(disconnected_at) = player.disconnected_at {
+                if disconnected_at.elapsed() >= state.config.reconnect_grace {
+                    let reason = format!("{} didn't reconnect in time", player.name);
+                    publish_result(&mut players, &mut match_state.spectators, &referee);
+                    players[1 - side].send(&Message::OpponentLeft);
+                    break 'relay reason;
+                }
+            }
+        }
+
+        if state.shutdown.is_requested() {
+            if !told_clients_about_shutdown {
+                let message = Message::ServerShuttingDown {
+                    seconds_left: state.shutdown.seconds_left(),
+                };
+                info!(?message, "Telling clients the relay is shutting down");
+                for player in players.iter_mut() {
+                    player.send(&message);
+                }
+                for spectator in match_state.spectators.iter_mut() {
+                    spectator.connection.send(&message).unwrap_or_default();
+                }
+                told_clients_about_shutdown = true;
+            }
+            if state.shutdown.deadline_passed() {
+                publish_result(&mut players, &mut match_state.spectators, &referee);
+                break String::from("relay shutdown timeout reached");
+            }
+        }
+
+        thread::sleep(time::Duration::from_millis(1));
+    };
+
+    if let Some(dir) = &state.config.record_dir {
+        let [host, joiner] = players;
+        let replay = Replay {
+            lobby_code,
+            seed,
+            config: match_state.settings.config.unwrap_or_default(),
+            started_at: unix_millis(started_at),
+            ended_at: unix_millis(SystemTime::now()),
+            end_reason: reason.clone(),
+            inputs: [
+                host.recorded_inputs.unwrap_or_default(),
+                joiner.recorded_inputs.unwrap_or_default(),
+            ],
+        };
+        match replay.save_in(dir) {
+            Ok(path) => info!(path = %path.display(), frames = replay.frames(), "Saved recording"),
+            Err(e) => warn!(error = %e, "Couldn't save the recording"),
+        }
+    }
+    reason
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::sim::GAME_CONFIG;
+    use common::validation::InputViolation;
+    use std::net::TcpListener;
+
+    // a player as the relay sees them, and the client's end of their connection
+    fn connect(
+        name: &'static str,
+        listener: &TcpListener,
+        metrics: &Arc<Metrics>,
+    ) -> (Player, Connection) {
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let guard = ConnectionGuard::new(Arc::clone(metrics));
+        (
+            Player::new(name, stream, guard, false),
+            Connection::new(client).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_host_forfeits_unusable_settings() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let metrics = Arc::new(Metrics::new());
+        let (host, mut host_client) = connect("host", &listener, &metrics);
+        let (joiner, mut joiner_client) = connect("joiner", &listener, &metrics);
+        let mut players = [host, joiner];
+        let mut match_state = MatchState {
+            seed: 1,
+            settings: AgreedSettings::default(),
+            latest_snapshot: None,
+            spectators: Vec::new(),
+            metrics,
+        };
+        let mut referee = Some(Referee::new(1));
+
+        // a ball that doesn't fit in the arena would panic the referee's simulation
+        let config = PongGameConfig {
+            ball_size: 500.0,
+            ..GAME_CONFIG
+        };
+        host_client.send(&Message::MatchConfig { config }).unwrap();
+        let deadline = time::Instant::now() + time::Duration::from_secs(5);
+        while referee.as_ref().unwrap().forfeit.is_none() {
+            assert!(
+                time::Instant::now() < deadline,
+                "the settings never arrived"
+            );
+            relay_messages(&mut players, &mut match_state, &mut referee, 0).unwrap();
+            thread::sleep(time::Duration::from_millis(1));
+        }
+        assert!(matches!(
+            referee.unwrap().forfeit,
+            Some((0, Forfeit::Settings(_)))
+        ));
+
+        // they're not kept or passed on to the joiner
+        assert_eq!(match_state.settings.config, None);
+        thread::sleep(time::Duration::from_millis(50));
+        let (messages, error) = joiner_client.receive();
+        assert!(messages.is_empty());
+        assert!(error.is_none());
+    }
+
+    #[test]
+    fn test_player_forfeits_racing_ahead() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let metrics = Arc::new(Metrics::new());
+        let (host, mut host_client) = connect("host", &listener, &metrics);
+        let (joiner, _joiner_client) = connect("joiner", &listener, &metrics);
+        let mut players = [host, joiner];
+        let mut match_state = MatchState {
+            seed: 1,
+            settings: AgreedSettings::default(),
+            latest_snapshot: None,
+            spectators: Vec::new(),
+            metrics,
+        };
+        let mut referee = Some(Referee::new(1));
+
+        // the joiner hasn't sent anything, an honest host would have stopped to wait for them
+        let newest_allowed = GAME_CONFIG.max_frames_ahead_of_opponent();
+        for frame in 0..=newest_allowed + 1 {
+            host_client
+                .send(&Message::Input(PongInputState::new(frame, 0.0)))
+                .unwrap();
+        }
+        let deadline = time::Instant::now() + time::Duration::from_secs(5);
+        while referee.as_ref().unwrap().forfeit.is_none() {
+            assert!(time::Instant::now() < deadline, "the inputs never arrived");
+            relay_messages(&mut players, &mut match_state, &mut referee, 0).unwrap();
+            thread::sleep(time::Duration::from_millis(1));
+        }
+        assert_eq!(
+            referee.unwrap().forfeit,
+            Some((
+                0,
+                Forfeit::Input(InputViolation::FrameTooFarAhead {
+                    newest_allowed,
+                    got: newest_allowed + 1
+                })
+            ))
+        );
+        assert_eq!(players[0].next_frame_expected(), newest_allowed + 1);
+    }
+}
@@ -0,0 +1,98 @@
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+/// Shared between every thread so they can notice the relay is draining. Once a shutdown
+/// is requested no new lobbies or joins are accepted, and running matches have until
+/// the deadline to finish
+pub struct Shutdown {
+    requested_at: Mutex<Option<Instant>>,
+    timeout: Duration,
+}
+
+impl Shutdown {
+    pub fn new(timeout: Duration) -> Self {
+        Shutdown {
+            requested_at: Mutex::new(None),
+            timeout,
+        }
+    }
+
+    /// Returns false if a shutdown was already requested
+    pub fn request(&self) -> bool {
+        let mut requested_at = self.requested_at.lock().unwrap();
+        if requested_at.is_some() {
+            return false;
+        }
+        *requested_at = Some(Instant::now());
+        true
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested_at.lock().unwrap().is_some()
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.requested_at
+            .lock()
+            .unwrap()
+            .map(|requested_at| requested_at + self.timeout)
+    }
+
+    pub fn deadline_passed(&self) -> bool {
+        match self.deadline() {
+            Some(deadline) => Instant::now() >= deadline,
+            None => false,
+        }
+    }
+
+    /// Rounded up so clients never get told 0 while there's still time left
+    pub fn seconds_left(&self) -> u32 {
+        match self.deadline() {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                left.as_millis().div_ceil(1000) as u32
+            }
+            None => self.timeout.as_secs() as u32,
+        }
+    }
+}
+
+/// SIGINT and SIGTERM start draining the relay, a second one exits immediately
+pub fn install_signal_handler(shutdown: Arc<Shutdown>) {
+    ctrlc::set_handler(move || {
+        if shutdown.request() {
+            info!(
+                timeout_secs = shutdown.timeout.as_secs(),
+                "Shutdown requested, draining. Signal again to exit immediately"
+            );
+        } else {
+            warn!("Second shutdown signal, exiting without waiting for matches");
+            process::exit(1);
+        }
+    })
+    .expect("Failed to install signal handler");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_once() {
+        let shutdown = Shutdown::new(Duration::from_secs(10));
+        assert!(!shutdown.is_requested());
+        assert!(!shutdown.deadline_passed());
+        assert!(shutdown.request());
+        assert!(!shutdown.request());
+        assert!(shutdown.is_requested());
+        assert_eq!(shutdown.seconds_left(), 10);
+
+        let no_time = Shutdown::new(Duration::from_secs(0));
+        no_time.request();
+        assert!(no_time.deadline_passed());
+        assert_eq!(no_time.seconds_left(), 0);
+    }
+}
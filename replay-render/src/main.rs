@@ -0,0 +1,209 @@
+// Renders a replay to a PNG per frame without a window or a GPU, for making highlights on
+// machines raylib can't run on.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use common::replay::Replay;
+use common::sim::PongGameState;
+
+mod raster;
+use raster::{Canvas, Color};
+
+const USAGE: &str = "usage: replay-render REPLAY OUT_DIR [--from FRAME] [--to FRAME] \
+                     [--every FRAMES] [--size WIDTHxHEIGHT]";
+
+struct RenderArgs {
+    replay: PathBuf,
+    out_dir: PathBuf,
+    // frames are numbered by how many have been played, 0 is the state before the first one
+    from: u32,
+    to: Option<u32>,
+    every: u32,
+    // width and height of the images, the replay's arena size if not given
+    size: Option<(u32, u32)>,
+}
+
+fn parse_number(flag: &str, value: Option<String>) -> Result<u32, String> {
+    let value = value.ok_or(format!("{} needs a number", flag))?;
+    value
+        .parse::<u32>()
+        .map_err(|e| format!("bad {} '{}': {}", flag, value, e))
+}
+
+impl RenderArgs {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<RenderArgs, String> {
+        let mut positional = Vec::new();
+        let mut render_args = RenderArgs {
+            replay: PathBuf::new(),
+            out_dir: PathBuf::new(),
+            from: 0,
+            to: None,
+            every: 1,
+            size: None,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--from" => render_args.from = parse_number(&arg, args.next())?,
+                "--to" => render_args.to = Some(parse_number(&arg, args.next())?),
+                "--every" => {
+                    render_args.every = parse_number(&arg, args.next())?;
+                    if render_args.every == 0 {
+                        return Err(String::from("--every has to be at least 1"));
+                    }
+                }
+                "--size" => {
+                    let size = args.next().ok_or("--size needs WIDTHxHEIGHT")?;
+                    render_args.size = Some(
+                        size.split_once('x')
+                            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                            .filter(|(w, h)| *w > 0 && *h > 0)
+                            .ok_or(format!("bad --size '{}', expected WIDTHxHEIGHT", size))?,
+                    );
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown argument {}", arg)),
+                _ => positional.push(PathBuf::from(arg)),
+            }
+        }
+
+        if positional.len() != 2 {
+            return Err(String::from("expected a replay and an output directory"));
+        }
+        render_args.out_dir = positional.pop().unwrap();
+        render_args.replay = positional.pop().unwrap();
+        Ok(render_args)
+    }
+}
+
+/// Draws the state the way the game does
+fn render_state(canvas: &mut Canvas, state: &PongGameState) {
+    let config = &state.config;
+    canvas.clear(Color::WHITE);
+    for paddle in [&state.left_paddle, &state.right_paddle].iter() {
+        canvas.fill_rect(
+            paddle.position.x,
+            paddle.position.y,
+            config.paddle_size.x,
+            config.paddle_size.y,
+            Color::BLACK,
+        );
+    }
+    canvas.fill_circle(
+        state.ball.position.x,
+        state.ball.position.y,
+        config.ball_size,
+        Color::RED,
+    );
+    for score in [&state.left_score, &state.right_score].iter() {
+        let middle_x = if score.left_side {
+            config.arena_size.x / 4.0
+        } else {
+            (3.0 * config.arena_size.x) / 4.0
+        };
+        canvas.draw_number(
+            score.value,
+            middle_x,
+            20.0,
+            config.score_font_size as f32,
+            Color::BLACK,
+        );
+    }
+}
+
+fn run(args: &RenderArgs) -> Result<u32, String> {
+    let replay = Replay::load(&args.replay)
+        .map_err(|e| format!("can't load {}: {}", args.replay.display(), e))?;
+    let to = args.to.unwrap_or(replay.frames());
+    if to > replay.frames() || args.from > to {
+        return Err(format!(
+            "frames {} to {} aren't in the replay, it has {}",
+            args.from,
+            to,
+            replay.frames()
+        ));
+    }
+    fs::create_dir_all(&args.out_dir)
+        .map_err(|e| format!("can't create {}: {}", args.out_dir.display(), e))?;
+
+    let arena_size = replay.config.arena_size;
+    let (width, height) = args
+        .size
+        .unwrap_or((arena_size.x as u32, arena_size.y as u32));
+    let mut canvas = Canvas::new(width, height, arena_size.x, arena_size.y);
+    let mut state = replay.initial_state();
+    let mut rendered = 0;
+    for frame in 0..=to {
+        if frame >= args.from && (frame - args.from).is_multiple_of(args.every) {
+            render_state(&mut canvas, &state);
+            let path = args.out_dir.join(format!("frame_{:06}.png", frame));
+            canvas
+                .write_png(&path)
+                .map_err(|e| format!("can't write {}: {}", path.display(), e))?;
+            rendered += 1;
+        }
+        if frame < to {
+            state.process_logic(&replay.inputs_for(frame));
+        }
+    }
+    Ok(rendered)
+}
+
+fn main() {
+    let args = match RenderArgs::from_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    match run(&args) {
+        Ok(rendered) => println!("Rendered {} frames to {}", rendered, args.out_dir.display()),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<RenderArgs, String> {
+        RenderArgs::from_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&["match.pongreplay", "out"]).unwrap();
+        assert_eq!(args.replay, PathBuf::from("match.pongreplay"));
+        assert_eq!(args.out_dir, PathBuf::from("out"));
+        assert_eq!((args.from, args.to, args.every), (0, None, 1));
+        assert_eq!(args.size, None);
+
+        let args = parse(&[
+            "--from",
+            "60",
+            "match.pongreplay",
+            "--to",
+            "120",
+            "out",
+            "--size",
+            "640x480",
+            "--every",
+            "2",
+        ])
+        .unwrap();
+        assert_eq!((args.from, args.to, args.every), (60, Some(120), 2));
+        assert_eq!(args.size, Some((640, 480)));
+
+        assert!(parse(&["match.pongreplay"]).is_err());
+        assert!(parse(&["a", "b", "c"]).is_err());
+        assert!(parse(&["a", "b", "--size", "640"]).is_err());
+        assert!(parse(&["a", "b", "--every", "0"]).is_err());
+        assert!(parse(&["a", "b", "--bogus"]).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+// Just enough of a CPU rasterizer to draw what the game draws: filled rectangles, filled circles
+// and numbers. Everything is positioned in arena units and scaled to the canvas size.
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const WHITE: Color = Color::new(255, 255, 255);
+    pub const BLACK: Color = Color::new(0, 0, 0);
+    // raylib's RED, so frames look like the game
+    pub const RED: Color = Color::new(230, 41, 55);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+}
+
+// 3x5 glyphs for the digits and a minus sign, one row per entry with the leftmost cell in the
+// highest of the three bits
+const DIGIT_GLYPHS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+const MINUS_GLYPH: [u8; 5] = [0b000, 0b000, 0b111, 0b000, 0b000];
+
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    // arena units to pixels
+    scale_x: f32,
+    scale_y: f32,
+    // RGB, row by row from the top
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32, arena_width: f32, arena_height: f32) -> Canvas {
+        Canvas {
+            width,
+            height,
+            scale_x: width as f32 / arena_width,
+            scale_y: height as f32 / arena_height,
+            pixels: vec![0; (width * height * 3) as usize],
+        }
+    }
+
+    #[cfg(test)]
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let i = ((y * self.width + x) * 3) as usize;
+        Color::new(self.pixels[i], self.pixels[i + 1], self.pixels[i + 2])
+    }
+
+    fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
+        let i = ((y * self.width + x) * 3) as usize;
+        self.pixels[i..i + 3].copy_from_slice(&[color.r, color.g, color.b]);
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        for pixel in self.pixels.chunks_exact_mut(3) {
+            pixel.copy_from_slice(&[color.r, color.g, color.b]);
+        }
+    }
+
+    // the pixel columns or rows from `start` to `end` in arena units, clipped to the canvas
+    fn pixel_span(start: f32, end: f32, scale: f32, limit: u32) -> std::ops::Range<u32> {
+        let clip = |value: f32| (value * scale).round().clamp(0.0, limit as f32) as u32;
+        clip(start)..clip(end)
+    }
+
+    pub fn fill_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color) {
+        for py in Canvas::pixel_span(y, y + height, self.scale_y, self.height) {
+            for px in Canvas::pixel_span(x, x + width, self.scale_x, self.width) {
+                self.set_pixel(px, py, color);
+            }
+        }
+    }
+
+    /// Fills every pixel whose center is inside the circle
+    pub fn fill_circle(&mut self, center_x: f32, center_y: f32, radius: f32, color: Color) {
+        let rows = Canvas::pixel_span(
+            center_y - radius - 1.0,
+            center_y + radius + 1.0,
+            self.scale_y,
+            self.height,
+        );
+        let columns = Canvas::pixel_span(
+            center_x - radius - 1.0,
+            center_x + radius + 1.0,
+            self.scale_x,
+            self.width,
+        );
+        for py in rows {
+            let dy = (py as f32 + 0.5) / self.scale_y - center_y;
+            for px in columns.clone() {
+                let dx = (px as f32 + 0.5) / self.scale_x - center_x;
+                if dx * dx + dy * dy <= radius * radius {
+                    self.set_pixel(px, py, color);
+                }
+            }
+        }
+    }
+
+    /// Draws `value` centered on `middle_x` with its top at `top`, `height` tall
+    pub fn draw_number(&mut self, value: i32, middle_x: f32, top: f32, height: f32, color: Color) {
+        let text = value.to_string();
+        let cell = height / 5.0;
+        // 3 cells per glyph with a cell between each
+        let width = cell * (text.len() as f32 * 4.0 - 1.0);
+        let mut left = middle_x - width / 2.0;
+        for c in text.chars() {
+            let glyph = match c.to_digit(10) {
+                Some(digit) => DIGIT_GLYPHS[digit as usize],
+                None => MINUS_GLYPH,
+            };
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        self.fill_rect(
+                            left + column as f32 * cell,
+                            top + row as f32 * cell,
+                            cell,
+                            cell,
+                            color,
+                        );
+                    }
+                }
+            }
+            left += cell * 4.0;
+        }
+    }
+
+    pub fn write_png(&self, path: &Path) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_rect_scales_and_clips() {
+        // half size, so every arena unit is half a pixel
+        let mut canvas = Canvas::new(50, 40, 100.0, 80.0);
+        canvas.clear(Color::WHITE);
+        canvas.fill_rect(10.0, 20.0, 20.0, 10.0, Color::BLACK);
+        assert_eq!(canvas.pixel(5, 10), Color::BLACK);
+        assert_eq!(canvas.pixel(14, 14), Color::BLACK);
+        assert_eq!(canvas.pixel(15, 14), Color::WHITE);
+        assert_eq!(canvas.pixel(5, 15), Color::WHITE);
+        assert_eq!(canvas.pixel(4, 10), Color::WHITE);
+
+        // hanging off the edge is fine
+        canvas.fill_rect(90.0, -10.0, 50.0, 20.0, Color::RED);
+        assert_eq!(canvas.pixel(49, 0), Color::RED);
+    }
+
+    #[test]
+    fn test_fill_circle() {
+        let mut canvas = Canvas::new(100, 100, 100.0, 100.0);
+        canvas.clear(Color::WHITE);
+        canvas.fill_circle(50.0, 50.0, 10.0, Color::RED);
+        assert_eq!(canvas.pixel(50, 50), Color::RED);
+        assert_eq!(canvas.pixel(58, 50), Color::RED);
+        assert_eq!(canvas.pixel(61, 50), Color::WHITE);
+        // the corner of the bounding box is outside the circle
+        assert_eq!(canvas.pixel(58, 58), Color::WHITE);
+    }
+
+    #[test]
+    fn test_draw_number() {
+        let mut canvas = Canvas::new(100, 100, 100.0, 100.0);
+        canvas.clear(Color::WHITE);
+        // "1" is 3 cells wide, cells are 10 units, so it spans 35 to 65
+        canvas.draw_number(1, 50.0, 0.0, 50.0, Color::BLACK);
+        assert_eq!(canvas.pixel(45, 5), Color::BLACK); // top of the stem
+        assert_eq!(canvas.pixel(36, 5), Color::WHITE);
+        assert_eq!(canvas.pixel(36, 15), Color::BLACK); // the flag
+        assert_eq!(canvas.pixel(36, 45), Color::BLACK); // the base
+        assert_eq!(canvas.pixel(45, 55), Color::WHITE);
+    }
+}